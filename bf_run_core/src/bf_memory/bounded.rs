@@ -0,0 +1,155 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::mem::offset_of;
+
+use super::{BfMemory, TapeEdgePolicy};
+use crate::{
+	cell::BfCell,
+	executors::{bf_recompiler::RecompiledOps, RuntimeError},
+};
+
+const BF_MEMORY_BOUNDED_SIZE: usize = 30000;
+
+/// Memory with a fixed number of values, that never grows.
+/// Index 0 is the first value of the tape, and edge_policy decides what moving the memory pointer
+/// before the first value, or after the last value, does. The pointer never leaves the tape,
+/// so the executors do not optimize the operations, as the optimized operations merge the moves of the pointer.
+#[derive(Debug)]
+pub struct BfMemoryBounded<C> {
+	array:       Box<[C]>,
+	/// Address and length of array, read by the recompiled code.
+	array_addr:  usize,
+	array_len:   u32,
+	edge_policy: TapeEdgePolicy,
+}
+impl<C: BfCell> BfMemoryBounded<C> {
+	/// Position in array of the value at index.
+	fn position(&self, index: i32) -> Result<usize, RuntimeError> {
+		match usize::try_from(index) {
+			Ok(position) if position < self.array.len() => Ok(position),
+			_ => Err(RuntimeError::PointerOutOfBounds(index as isize)),
+		}
+	}
+}
+impl<C: BfCell> BfMemory for BfMemoryBounded<C> {
+	type Cell = C;
+
+	const CHECKED_ACCESS: bool = true;
+
+	fn new(custom_size: Option<usize>) -> BfMemoryBounded<C> {
+		// The length must fit in an i32, as the indexes do.
+		let size = custom_size.unwrap_or(BF_MEMORY_BOUNDED_SIZE).clamp(1, i32::MAX as usize);
+		let mut array = vec![C::ZERO; size].into_boxed_slice();
+		let array_addr = array.as_mut_ptr() as usize;
+		BfMemoryBounded { array, array_addr, array_len: size as u32, edge_policy: TapeEdgePolicy::default() }
+	}
+
+	fn with_edge_policy(mut self, edge_policy: TapeEdgePolicy) -> BfMemoryBounded<C> {
+		self.edge_policy = edge_policy;
+		self
+	}
+
+	fn get_ref(&mut self, index: i32) -> &mut C {
+		match self.try_get_ref(index) {
+			Ok(value) => value,
+			Err(err) => panic!("{}", err),
+		}
+	}
+
+	fn try_get_ref(&mut self, index: i32) -> Result<&mut C, RuntimeError> {
+		let position = self.position(index)?;
+		Ok(&mut self.array[position])
+	}
+
+	fn move_pointer(&self, index: i32, move_value: i32) -> Result<i32, RuntimeError> {
+		let len = self.array_len as i64;
+		let index = index as i64 + move_value as i64;
+		match self.edge_policy {
+			TapeEdgePolicy::Error if (0..len).contains(&index) => Ok(index as i32),
+			TapeEdgePolicy::Error => Err(RuntimeError::PointerOutOfBounds(index as isize)),
+			TapeEdgePolicy::Wrap => Ok(index.rem_euclid(len) as i32),
+			TapeEdgePolicy::Clamp => Ok(index.clamp(0, len - 1) as i32),
+		}
+	}
+
+	fn allows_optimizations(&self) -> bool {
+		false
+	}
+
+	fn get_value(&self, index: i32) -> C {
+		self.position(index).map_or(C::ZERO, |position| self.array[position])
+	}
+
+	fn scan_zero(&mut self, index: i32, stride: i32) -> Result<i32, RuntimeError> {
+		self.standard_scan_zero(index, stride)
+	}
+
+	fn get_move_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, move_value: i32) -> RecompiledOps {
+		// Indexes inside of the tape are looked up inline, the others are left to move_ref and edge_policy.
+		let move_ref_ops = BfMemoryBounded::<C>::get_standard_move_ops(context_addr, memory_addr, move_ref_fn_addr, move_value);
+
+		let mut recompiled_memory = RecompiledOps::default();
+		recompiled_memory.push_opcodes(&[0x8d, 0xb1]); // lea esi, [rcx + next argument]
+		recompiled_memory.push_opcodes(&move_value.to_ne_bytes()); // argument for lea.
+		recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		recompiled_memory.push_opcodes(&memory_addr.to_ne_bytes()); // memory_addr as argument for movabs rdi.
+		recompiled_memory.push_opcodes(&[0x3b, 0xb7]); // cmp esi, [rdi + next argument]
+		recompiled_memory.push_opcodes(&(offset_of!(Self, array_len) as u32).to_ne_bytes());
+		let mut inline_ops = RecompiledOps::default();
+		inline_ops.push_opcodes(&[0x89, 0xf1]); // mov ecx, esi
+		inline_ops.push_opcodes(&[0x48, 0x8b, 0x87]); // mov rax, [rdi + next argument]
+		inline_ops.push_opcodes(&(offset_of!(Self, array_addr) as u32).to_ne_bytes());
+		inline_ops.push_opcodes(&[0x48, 0x8d, 0x04, scale_bits::<C>(0x08)]); // lea rax, [rax + rcx * size]
+		inline_ops.push_opcodes(&[0xeb, move_ref_ops.len() as u8]); // Jump over the call to move_ref.
+		// Negative indexes are above the length, when compared as unsigned values.
+		recompiled_memory.push_opcodes(&[0x73, inline_ops.len() as u8]); // Jump above or equal, to the call to move_ref.
+		recompiled_memory.push_opcodes(&inline_ops);
+		recompiled_memory.push_opcodes(&move_ref_ops);
+		recompiled_memory
+	}
+
+	fn get_offset_ref_ops(context_addr: [u8; 8], memory_addr: usize, get_offset_ref_fn_addr: usize, offset: i32) -> RecompiledOps {
+		let standard_ops = BfMemoryBounded::<C>::get_standard_offset_ref_ops(context_addr, memory_addr, get_offset_ref_fn_addr, offset);
+
+		let mut recompiled_memory = RecompiledOps::default();
+		recompiled_memory.push_opcodes(&[0x8d, 0xb1]); // lea esi, [rcx + next argument]
+		recompiled_memory.push_opcodes(&offset.to_ne_bytes()); // argument for lea.
+		recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		recompiled_memory.push_opcodes(&memory_addr.to_ne_bytes()); // memory_addr as argument for movabs rdi.
+		recompiled_memory.push_opcodes(&[0x3b, 0xb7]); // cmp esi, [rdi + next argument]
+		recompiled_memory.push_opcodes(&(offset_of!(Self, array_len) as u32).to_ne_bytes());
+		let mut inline_ops = RecompiledOps::default();
+		inline_ops.push_opcodes(&[0x48, 0x8b, 0xbf]); // mov rdi, [rdi + next argument]
+		inline_ops.push_opcodes(&(offset_of!(Self, array_addr) as u32).to_ne_bytes());
+		inline_ops.push_opcodes(&[0x48, 0x8d, 0x34, scale_bits::<C>(0x37)]); // lea rsi, [rdi + rsi * size]
+		inline_ops.push_opcodes(&[0xeb, standard_ops.len() as u8]); // Jump over the call to get_offset_ref.
+		recompiled_memory.push_opcodes(&[0x73, inline_ops.len() as u8]); // Jump above or equal, to the call to get_offset_ref.
+		recompiled_memory.push_opcodes(&inline_ops);
+		recompiled_memory.push_opcodes(&standard_ops);
+		recompiled_memory
+	}
+
+	fn get_scan_zero_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, stride: i32) -> RecompiledOps {
+		BfMemoryBounded::<C>::get_standard_scan_zero_ops(context_addr, memory_addr, move_ref_fn_addr, stride)
+	}
+}
+
+/// Adds the scale factor of the size of C, to the sib byte of an x86-64 instruction.
+fn scale_bits<C: BfCell>(sib: u8) -> u8 {
+	sib | (C::SIZE.trailing_zeros() as u8) << 6
+}
@@ -0,0 +1,254 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{cell::Cell, ops::Range, sync::Once};
+
+use super::{BfMemory, BfMemoryMemUnsafe, BF_MEMORY_UNSAFE_SIZE};
+use crate::{
+	cell::BfCell,
+	executors::{bf_recompiler::RecompiledOps, RuntimeError},
+};
+
+thread_local! {
+	/// Set while recompiled code runs on the thread, so its faults in the guard regions can continue at the recovery code.
+	/// Faults are handled on the thread that caused them, so threads running recompiled code do not affect each other.
+	static FAULT_RECOVERY: Cell<Option<FaultRecovery>> = const { Cell::new(None) };
+	/// Offset from index 0 of the last access to a guard region by recompiled code on the thread.
+	static OUT_OF_BOUNDS: Cell<Option<isize>> = const { Cell::new(None) };
+}
+
+static INSTALL_HANDLER: Once = Once::new();
+static mut PREVIOUS_ACTION: Option<libc::sigaction> = None;
+
+/// Addresses of the mapping of a guarded memory, for attributing faults of the recompiled code to it.
+#[derive(Debug, Clone, Copy)]
+pub struct GuardRegion {
+	/// Addresses of the mapping, including the guard regions.
+	mapping_start: usize,
+	mapping_end:   usize,
+	/// Address of the value at index 0.
+	center:        usize,
+	cell_size:     usize,
+}
+
+/// Only read by continue_at_recovery on platforms where the recompiled code accesses the guard regions.
+#[cfg_attr(not(all(target_os = "linux", target_arch = "x86_64")), allow(dead_code))]
+#[derive(Clone, Copy)]
+struct FaultRecovery {
+	code_start:    usize,
+	code_end:      usize,
+	recovery_addr: usize,
+	region:        GuardRegion,
+}
+
+/// Memory with a fixed size tape, surrounded by guard pages that can not be accessed.
+/// The length of the tape is rounded up to a whole number of pages, and index 0 is in the middle of it.
+///
+/// The recompiled code moves the memory pointer as cheaply as with BfMemoryMemUnsafe,
+/// and accessing a value outside of the tape faults in a guard region. On x86-64 the recompiled code
+/// continues at a recovery routine after the fault, and stops with a PointerOutOfBounds error.
+/// The other executors, and the recompiler on other processor architectures, check the index
+/// in try_get_ref instead, so they stop with the same error without touching the guard regions.
+pub struct BfMemoryMemGuarded<C> {
+	mapping:     *mut u8,
+	/// Size in bytes of the mapping, including the guard regions.
+	mapping_len: usize,
+	tape_start:  *mut C,
+	/// Number of values in the tape.
+	tape_len:    usize,
+}
+impl<C: BfCell> BfMemoryMemGuarded<C> {
+	/// Size in bytes of the inaccessible memory on each side of the tape.
+	/// Large enough that moving by any i32 from inside the tape ends up in the tape or a guard region.
+	const GUARD_SIZE: usize = (1 << 31) * C::SIZE;
+
+	fn tape(&self) -> &[C] {
+		unsafe { std::slice::from_raw_parts(self.tape_start, self.tape_len) }
+	}
+
+	fn tape_mut(&mut self) -> &mut [C] {
+		unsafe { std::slice::from_raw_parts_mut(self.tape_start, self.tape_len) }
+	}
+
+	/// Position in the tape of the value at index, if it is inside of the tape.
+	fn position(&self, index: i32) -> Option<usize> {
+		usize::try_from(index as i64 + (self.tape_len / 2) as i64)
+			.ok()
+			.filter(|position| *position < self.tape_len)
+	}
+
+	fn center(&self) -> *mut C {
+		unsafe { self.tape_start.add(self.tape_len / 2) }
+	}
+}
+impl<C: BfCell> BfMemory for BfMemoryMemGuarded<C> {
+	type Cell = C;
+
+	const CHECKED_ACCESS: bool = true;
+
+	fn new(custom_size: Option<usize>) -> BfMemoryMemGuarded<C> {
+		let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+		let tape_bytes = (custom_size.unwrap_or(BF_MEMORY_UNSAFE_SIZE).max(1) * C::SIZE).next_multiple_of(page_size);
+		let mapping_len = tape_bytes + 2 * Self::GUARD_SIZE;
+		let mapping = unsafe {
+			libc::mmap(
+				std::ptr::null_mut(),
+				mapping_len,
+				libc::PROT_NONE,
+				libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+				-1,
+				0,
+			)
+		};
+		if mapping == libc::MAP_FAILED {
+			panic!("Could not map guarded memory: {}", std::io::Error::last_os_error());
+		}
+		let mapping = mapping as *mut u8;
+		let tape_start = unsafe { mapping.add(Self::GUARD_SIZE) };
+		if unsafe { libc::mprotect(tape_start as *mut libc::c_void, tape_bytes, libc::PROT_READ | libc::PROT_WRITE) } != 0 {
+			panic!("Could not make guarded memory accessible: {}", std::io::Error::last_os_error());
+		}
+
+		INSTALL_HANDLER.call_once(install_handler);
+		BfMemoryMemGuarded { mapping, mapping_len, tape_start: tape_start as *mut C, tape_len: tape_bytes / C::SIZE }
+	}
+
+	fn get_ref(&mut self, index: i32) -> &mut C {
+		match self.try_get_ref(index) {
+			Ok(value) => value,
+			Err(err) => panic!("{}", err),
+		}
+	}
+
+	fn try_get_ref(&mut self, index: i32) -> Result<&mut C, RuntimeError> {
+		match self.position(index) {
+			Some(position) => Ok(&mut self.tape_mut()[position]),
+			None => Err(RuntimeError::PointerOutOfBounds(index as isize)),
+		}
+	}
+
+	fn guard_region(&self) -> Option<GuardRegion> {
+		Some(GuardRegion {
+			mapping_start: self.mapping as usize,
+			mapping_end:   self.mapping as usize + self.mapping_len,
+			center:        self.center() as usize,
+			cell_size:     C::SIZE,
+		})
+	}
+
+	fn get_value(&self, index: i32) -> C {
+		self.position(index).map_or(C::ZERO, |position| self.tape()[position])
+	}
+
+	fn scan_zero(&mut self, index: i32, stride: i32) -> Result<i32, RuntimeError> {
+		self.standard_scan_zero(index, stride)
+	}
+
+	fn get_move_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, move_value: i32) -> RecompiledOps {
+		BfMemoryMemUnsafe::<C>::get_move_ops(context_addr, memory_addr, move_ref_fn_addr, move_value)
+	}
+
+	fn get_offset_ref_ops(context_addr: [u8; 8], memory_addr: usize, get_offset_ref_fn_addr: usize, offset: i32) -> RecompiledOps {
+		BfMemoryMemUnsafe::<C>::get_offset_ref_ops(context_addr, memory_addr, get_offset_ref_fn_addr, offset)
+	}
+
+	fn get_scan_zero_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, stride: i32) -> RecompiledOps {
+		// The vectorized scan of the unsafe memory reads values past the zero value, which may be in a guard region.
+		BfMemoryMemGuarded::<C>::get_standard_scan_zero_ops(context_addr, memory_addr, move_ref_fn_addr, stride)
+	}
+}
+impl<C> Drop for BfMemoryMemGuarded<C> {
+	fn drop(&mut self) {
+		unsafe { libc::munmap(self.mapping as *mut libc::c_void, self.mapping_len) };
+	}
+}
+impl<C: BfCell> std::fmt::Debug for BfMemoryMemGuarded<C> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("BfMemoryMemGuarded").field("array", &self.tape()).finish()
+	}
+}
+
+/// Lets faults in the guard regions of region, caused by the machine code in code on the current thread,
+/// continue at recovery_addr, until clear_fault_recovery is called.
+/// The recovery code must be able to return from the recompiled code,
+/// so the recompiled code must not have anything pushed to the stack when it accesses the memory.
+pub(crate) fn set_fault_recovery(code: Range<usize>, recovery_addr: usize, region: GuardRegion) {
+	let recovery = FaultRecovery { code_start: code.start, code_end: code.end, recovery_addr, region };
+	FAULT_RECOVERY.with(|fault_recovery| fault_recovery.set(Some(recovery)));
+}
+
+pub(crate) fn clear_fault_recovery() {
+	FAULT_RECOVERY.with(|fault_recovery| fault_recovery.set(None));
+}
+
+/// Returns the offset from index 0 of the last access to a guard region, if one happened on the current thread
+/// since the last call.
+pub(crate) fn take_out_of_bounds() -> Option<isize> {
+	OUT_OF_BOUNDS.with(Cell::take)
+}
+
+fn install_handler() {
+	unsafe {
+		let mut action: libc::sigaction = std::mem::zeroed();
+		action.sa_sigaction = handle_segv as *const () as usize;
+		action.sa_flags = libc::SA_SIGINFO;
+		libc::sigemptyset(&mut action.sa_mask);
+		let mut previous_action: libc::sigaction = std::mem::zeroed();
+		if libc::sigaction(libc::SIGSEGV, &action, &mut previous_action) == 0 {
+			PREVIOUS_ACTION = Some(previous_action);
+		}
+	}
+}
+
+extern "C" fn handle_segv(_signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+	let addr = unsafe { (*info).si_addr() } as usize;
+	if let Some(recovery) = FAULT_RECOVERY.with(Cell::get) {
+		let region = recovery.region;
+		if (region.mapping_start..region.mapping_end).contains(&addr) && continue_at_recovery(context, &recovery) {
+			let offset = (addr as isize - region.center as isize).div_euclid(region.cell_size as isize);
+			OUT_OF_BOUNDS.with(|out_of_bounds| out_of_bounds.set(Some(offset)));
+			return;
+		}
+	}
+
+	// Not caused by the recompiled code accessing a guard region,
+	// let the previous handler take care of the fault, when it happens again.
+	unsafe {
+		match &*std::ptr::addr_of!(PREVIOUS_ACTION) {
+			Some(previous_action) => libc::sigaction(libc::SIGSEGV, previous_action, std::ptr::null_mut()),
+			None => libc::signal(libc::SIGSEGV, libc::SIG_DFL) as libc::c_int,
+		};
+	}
+}
+
+/// Moves the instruction pointer of the faulting thread to the recovery code, if the fault happened in recompiled code.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn continue_at_recovery(context: *mut libc::c_void, recovery: &FaultRecovery) -> bool {
+	let context = unsafe { &mut *(context as *mut libc::ucontext_t) };
+	let instruction_pointer = context.uc_mcontext.gregs[libc::REG_RIP as usize] as usize;
+	if (recovery.code_start..recovery.code_end).contains(&instruction_pointer) {
+		context.uc_mcontext.gregs[libc::REG_RIP as usize] = recovery.recovery_addr as i64;
+		true
+	}
+	else {
+		false
+	}
+}
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+fn continue_at_recovery(_context: *mut libc::c_void, _recovery: &FaultRecovery) -> bool {
+	false
+}
@@ -0,0 +1,423 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::{
+	cell::{BfCell, WrappingInteger},
+	executors::{bf_recompiler::RecompiledOps, RuntimeError},
+};
+
+/// What moving the memory pointer past either end of a memory with a fixed size does.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum TapeEdgePolicy {
+	/// Execution stops with RuntimeError::PointerOutOfBounds, and the pointer stays at the last value it was at.
+	#[default]
+	Error,
+	/// The pointer wraps around to the other end of the tape.
+	Wrap,
+	/// The pointer stays at the end of the tape.
+	Clamp,
+}
+
+pub trait BfMemory {
+	/// Type of the values in the memory.
+	type Cell: BfCell;
+	/// Whether try_get_ref can fail, in which case the references created by the machine code
+	/// of the memory may be null, after the error was stored through the trampoline function.
+	const CHECKED_ACCESS: bool = false;
+	fn new(custom_size: Option<usize>) -> Self;
+	/// Sets the edge policy of memories with a fixed size, other memories ignore it.
+	fn with_edge_policy(self, _edge_policy: TapeEdgePolicy) -> Self
+	where Self: Sized {
+		self
+	}
+	fn get_ref(&mut self, index: i32) -> &mut Self::Cell;
+	/// Like get_ref, but returns an error instead of panicking, if the value at index can not be accessed.
+	fn try_get_ref(&mut self, index: i32) -> Result<&mut Self::Cell, RuntimeError> {
+		Ok(self.get_ref(index))
+	}
+	/// Addresses of the guard regions that the recompiled code faults in, when it accesses a value outside of the memory.
+	/// None for memories that the recompiled code never accesses outside of their values.
+	fn guard_region(&self) -> Option<guarded::GuardRegion> {
+		None
+	}
+	/// Returns the index of the memory pointer after moving it from index by move_value.
+	/// Memories with a fixed size apply their edge policy, the others can move the pointer anywhere.
+	fn move_pointer(&self, index: i32, move_value: i32) -> Result<i32, RuntimeError> {
+		Ok(index + move_value)
+	}
+	/// Whether the executors may optimize the operations for the memory.
+	/// The optimized operations merge the moves of the memory pointer, and leave out the moves between the values they access,
+	/// so the memories that check every move in move_pointer are not optimized.
+	fn allows_optimizations(&self) -> bool {
+		true
+	}
+	/// Reads the value at index, without allocating memory for it.
+	fn get_value(&self, index: i32) -> Self::Cell;
+	/// Returns the first index with a value of zero, searching from index and moving by stride.
+	fn scan_zero(&mut self, index: i32, stride: i32) -> Result<i32, RuntimeError>;
+	fn standard_scan_zero(&mut self, index: i32, stride: i32) -> Result<i32, RuntimeError> {
+		let mut index = index;
+		while *self.try_get_ref(index)? != Self::Cell::ZERO {
+			index += stride;
+		}
+		Ok(index)
+	}
+	/// Creates the machine code for moving the memory pointer by move_value,
+	/// leaving the new index in "ecx" and a reference to the new current cell in "rax".
+	/// The current value is kept in "dl", "dx" or "edx", depending on the size of the cells,
+	/// and is already put back into memory.
+	/// context_addr is the first argument that must be passed to the function at move_ref_fn_addr,
+	/// and memory_addr is the address of the memory itself, which does not move while the recompiled code runs.
+	fn get_move_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, move_value: i32) -> RecompiledOps;
+	fn get_standard_move_ops(context_addr: [u8; 8], _memory_addr: usize, move_ref_fn_addr: usize, move_value: i32) -> RecompiledOps {
+		let mut recompiled_memory = RecompiledOps::default();
+		// The index is replaced by the moved index, by move_ref.
+		recompiled_memory.push(0x51); // Push rcx
+		recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		recompiled_memory.push_opcodes(&context_addr); // context_addr as argument for movabs rdi.
+		recompiled_memory.push(0xbe); // mov esi
+		recompiled_memory.push_opcodes(&move_value.to_ne_bytes()); // argument for mov esi.
+		recompiled_memory.push_opcodes(&[0x48, 0x89, 0xe2]); // mov rdx, rsp. Third argument, the pushed index.
+		recompiled_memory.add_fn_call(move_ref_fn_addr);
+		recompiled_memory.push(0x59); // Pop rcx
+		recompiled_memory
+	}
+	/// Creates the machine code for getting a reference to the value at offset from the current index into "rsi",
+	/// while "rax" keeps pointing to the current value.
+	/// context_addr is the first argument that must be passed to the function at get_offset_ref_fn_addr.
+	fn get_offset_ref_ops(context_addr: [u8; 8], memory_addr: usize, get_offset_ref_fn_addr: usize, offset: i32) -> RecompiledOps;
+	fn get_standard_offset_ref_ops(
+		context_addr: [u8; 8], _memory_addr: usize, get_offset_ref_fn_addr: usize, offset: i32,
+	) -> RecompiledOps {
+		let mut recompiled_memory = RecompiledOps::default();
+		// Fetching the reference may reallocate the memory, so "dl" is put back into memory first.
+		recompiled_memory.add_store_current::<Self::Cell>();
+		// The index is replaced by a reference to the current value, by get_offset_ref.
+		recompiled_memory.push(0x51); // Push rcx
+		recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		recompiled_memory.push_opcodes(&context_addr); // context_addr as argument for movabs rdi.
+		recompiled_memory.push(0xbe); // mov esi
+		recompiled_memory.push_opcodes(&offset.to_ne_bytes()); // argument for mov esi.
+		recompiled_memory.push_opcodes(&[0x48, 0x89, 0xe2]); // mov rdx, rsp. Third argument, the pushed index.
+		recompiled_memory.add_fn_call(get_offset_ref_fn_addr);
+		recompiled_memory.push_opcodes(&[0x48, 0x89, 0xc6]); // mov rsi, rax
+		recompiled_memory.push(0x58); // Pop rax
+		recompiled_memory.add_load_current::<Self::Cell>();
+		recompiled_memory
+	}
+	/// Creates the machine code for moving the memory pointer by stride until it points to a value of zero.
+	/// Leaves the new index in "ecx", a reference to the value in "rax", and the value in "dl".
+	/// context_addr is the first argument that must be passed to the function at move_ref_fn_addr.
+	fn get_scan_zero_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, stride: i32) -> RecompiledOps;
+	fn get_standard_scan_zero_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, stride: i32) -> RecompiledOps {
+		let mut scan_block = Self::get_move_ops(context_addr, memory_addr, move_ref_fn_addr, stride);
+		let mut compare_block = RecompiledOps::default();
+		compare_block.add_load_current::<Self::Cell>();
+		compare_block.add_compare_current_zero::<Self::Cell>();
+		let jump_back = -((scan_block.len() + compare_block.len()) as i32) - 6 - if Self::CHECKED_ACCESS { 5 } else { 0 };
+		compare_block.push_opcodes(&[0x0f, 0x85]); // Jump not equal, to the start of the scan block.
+		compare_block.push_opcodes(&jump_back.to_ne_bytes());
+		if Self::CHECKED_ACCESS {
+			// The scan stops with a null reference in "rax", when move_ref failed.
+			scan_block.push_opcodes(&[0x48, 0x85, 0xc0]); // test rax, rax
+			scan_block.push_opcodes(&[0x74, compare_block.len() as u8]); // Jump equal, out of the scan.
+		}
+		scan_block.push_opcodes(&compare_block);
+
+		let mut recompiled_memory = RecompiledOps::default();
+		recompiled_memory.add_compare_current_zero::<Self::Cell>();
+		let mut store_current = RecompiledOps::default();
+		store_current.add_store_current::<Self::Cell>();
+		recompiled_memory.push_opcodes(&[0x0f, 0x84]); // Jump equal, over the scan.
+		recompiled_memory.push_opcodes(&((scan_block.len() + store_current.len()) as i32).to_ne_bytes());
+		// Put value of "dl" back into its position in bf_memory, the values passed over are unchanged.
+		recompiled_memory.push_opcodes(&store_current);
+		recompiled_memory.push_opcodes(&scan_block);
+		recompiled_memory
+	}
+}
+
+#[derive(Debug)]
+pub struct BfMemoryMemSafe<C> {
+	negatives: Vec<C>,
+	positives: Vec<C>,
+}
+impl<C: BfCell> BfMemory for BfMemoryMemSafe<C> {
+	type Cell = C;
+
+	fn new(custom_size: Option<usize>) -> BfMemoryMemSafe<C> {
+		let size = custom_size.map_or(0, |val| val / 2);
+		BfMemoryMemSafe { negatives: Vec::with_capacity(size), positives: Vec::with_capacity(size) }
+	}
+
+	fn get_ref(&mut self, index: i32) -> &mut C {
+		let (index, vec) = if index < 0 {
+			(index.wrapping_neg() as usize, &mut self.negatives)
+		}
+		else {
+			(index as usize, &mut self.positives)
+		};
+		while vec.len() <= index {
+			vec.push(C::ZERO);
+		}
+		unsafe { vec.get_unchecked_mut(index) }
+	}
+
+	fn get_value(&self, index: i32) -> C {
+		let (index, vec) = if index < 0 {
+			(index.wrapping_neg() as usize, &self.negatives)
+		}
+		else {
+			(index as usize, &self.positives)
+		};
+		vec.get(index).copied().unwrap_or(C::ZERO)
+	}
+
+	fn scan_zero(&mut self, index: i32, stride: i32) -> Result<i32, RuntimeError> {
+		self.standard_scan_zero(index, stride)
+	}
+
+	fn get_move_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, move_value: i32) -> RecompiledOps {
+		BfMemoryMemSafe::<C>::get_standard_move_ops(context_addr, memory_addr, move_ref_fn_addr, move_value)
+	}
+
+	fn get_offset_ref_ops(context_addr: [u8; 8], memory_addr: usize, get_offset_ref_fn_addr: usize, offset: i32) -> RecompiledOps {
+		BfMemoryMemSafe::<C>::get_standard_offset_ref_ops(context_addr, memory_addr, get_offset_ref_fn_addr, offset)
+	}
+
+	fn get_scan_zero_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, stride: i32) -> RecompiledOps {
+		BfMemoryMemSafe::<C>::get_standard_scan_zero_ops(context_addr, memory_addr, move_ref_fn_addr, stride)
+	}
+}
+
+#[derive(Debug)]
+pub struct BfMemoryMemSafeSingleArray<C> {
+	vector: Vec<C>,
+}
+impl<C: BfCell> BfMemoryMemSafeSingleArray<C> {
+	#[inline(never)]
+	fn increase_memory(&mut self) {
+		let old_vec_len = self.vector.len();
+		let old_vec = std::mem::replace(&mut self.vector, Vec::with_capacity(old_vec_len * 2));
+
+		let zero_len = old_vec_len / 2;
+		for _i in 0..zero_len {
+			self.vector.push(C::ZERO);
+		}
+		for element in old_vec {
+			self.vector.push(element);
+		}
+		for _i in 0..zero_len {
+			self.vector.push(C::ZERO);
+		}
+	}
+}
+impl<C: BfCell> BfMemory for BfMemoryMemSafeSingleArray<C> {
+	type Cell = C;
+
+	fn new(custom_size: Option<usize>) -> BfMemoryMemSafeSingleArray<C> {
+		// No size below 2 can be passed, since it creates an infinite loop when trying to expand memory.
+		let size = custom_size.map_or(2, |size| if size > 1 { size } else { 2 });
+		BfMemoryMemSafeSingleArray { vector: vec![C::ZERO; size] }
+	}
+
+	fn get_ref(&mut self, index: i32) -> &mut C {
+		let vec_len = self.vector.len();
+		let new_pos = index + (vec_len / 2) as i32;
+		if new_pos > 0 && new_pos < vec_len as i32 {
+			unsafe { self.vector.get_unchecked_mut(new_pos as usize) }
+		}
+		else {
+			self.increase_memory();
+			self.get_ref(index)
+		}
+	}
+
+	fn get_value(&self, index: i32) -> C {
+		let new_pos = index as i64 + (self.vector.len() / 2) as i64;
+		usize::try_from(new_pos)
+			.ok()
+			.and_then(|new_pos| self.vector.get(new_pos))
+			.copied()
+			.unwrap_or(C::ZERO)
+	}
+
+	fn scan_zero(&mut self, index: i32, stride: i32) -> Result<i32, RuntimeError> {
+		self.standard_scan_zero(index, stride)
+	}
+
+	fn get_move_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, move_value: i32) -> RecompiledOps {
+		BfMemoryMemSafeSingleArray::<C>::get_standard_move_ops(context_addr, memory_addr, move_ref_fn_addr, move_value)
+	}
+
+	fn get_offset_ref_ops(context_addr: [u8; 8], memory_addr: usize, get_offset_ref_fn_addr: usize, offset: i32) -> RecompiledOps {
+		BfMemoryMemSafeSingleArray::<C>::get_standard_offset_ref_ops(context_addr, memory_addr, get_offset_ref_fn_addr, offset)
+	}
+
+	fn get_scan_zero_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, stride: i32) -> RecompiledOps {
+		BfMemoryMemSafeSingleArray::<C>::get_standard_scan_zero_ops(context_addr, memory_addr, move_ref_fn_addr, stride)
+	}
+}
+
+const BF_MEMORY_UNSAFE_SIZE: usize = 65535;
+/// Zeroed values on both sides of the unsafe memory, so the vectorized scans never read outside of the allocation.
+const BF_MEMORY_UNSAFE_PADDING: usize = 16;
+
+#[derive(Debug)]
+pub struct BfMemoryMemUnsafe<C> {
+	array: Vec<C>,
+}
+impl<C: BfCell> BfMemoryMemUnsafe<C> {
+	/// Moves "rax" by move_value cells with an inline lea, if the distance in bytes fits in the displacement.
+	fn get_inline_move_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, move_value: i32) -> RecompiledOps {
+		let Some(move_bytes) = move_value.checked_mul(C::SIZE as i32)
+		else {
+			return BfMemoryMemUnsafe::<C>::get_standard_move_ops(context_addr, memory_addr, move_ref_fn_addr, move_value);
+		};
+		let mut recompiled_memory = RecompiledOps::default();
+		// Modify the index.
+		recompiled_memory.push_opcodes(&[0x81, 0xc1]); // Add ecx
+		recompiled_memory.push_opcodes(&move_value.to_ne_bytes()); // argument for add to ecx
+		recompiled_memory.push_opcodes(&[0x48, 0x8d, 0x80]); // lea rax, [rax + next argument]
+		recompiled_memory.push_opcodes(&move_bytes.to_ne_bytes()); // argument for lea.
+
+		recompiled_memory
+	}
+
+	/// Puts a reference to the value at offset from "rax" into "rsi", with an inline lea,
+	/// if the distance in bytes fits in the displacement.
+	fn get_inline_offset_ref_ops(context_addr: [u8; 8], memory_addr: usize, get_offset_ref_fn_addr: usize, offset: i32) -> RecompiledOps {
+		let Some(offset_bytes) = offset.checked_mul(C::SIZE as i32)
+		else {
+			return BfMemoryMemUnsafe::<C>::get_standard_offset_ref_ops(context_addr, memory_addr, get_offset_ref_fn_addr, offset);
+		};
+		let mut recompiled_memory = RecompiledOps::default();
+		recompiled_memory.push_opcodes(&[0x48, 0x8d, 0xb0]); // lea rsi, [rax + next argument]
+		recompiled_memory.push_opcodes(&offset_bytes.to_ne_bytes()); // argument for lea.
+		recompiled_memory
+	}
+}
+impl<C: BfCell> BfMemory for BfMemoryMemUnsafe<C> {
+	type Cell = C;
+
+	fn new(custom_size: Option<usize>) -> BfMemoryMemUnsafe<C> {
+		let size = custom_size.map_or(BF_MEMORY_UNSAFE_SIZE, |val| val);
+		BfMemoryMemUnsafe { array: vec![C::ZERO; size + 2 * BF_MEMORY_UNSAFE_PADDING] }
+	}
+
+	fn get_ref(&mut self, index: i32) -> &mut C {
+		let center = (self.array.len() / 2) as i32;
+		unsafe { self.array.get_unchecked_mut((center + index) as usize) }
+	}
+
+	fn get_value(&self, index: i32) -> C {
+		let new_pos = index as i64 + (self.array.len() / 2) as i64;
+		usize::try_from(new_pos)
+			.ok()
+			.and_then(|new_pos| self.array.get(new_pos))
+			.copied()
+			.unwrap_or(C::ZERO)
+	}
+
+	fn scan_zero(&mut self, index: i32, stride: i32) -> Result<i32, RuntimeError> {
+		let center = (self.array.len() / 2) as i32;
+		let position = (center + index) as usize;
+		let found = match stride {
+			1 => self
+				.array
+				.get(position..)
+				.and_then(|values| values.iter().position(|value| *value == C::ZERO))
+				.map(|distance| index + distance as i32),
+			-1 => self
+				.array
+				.get(..=position)
+				.and_then(|values| values.iter().rposition(|value| *value == C::ZERO))
+				.map(|position| position as i32 - center),
+			_ => None,
+		};
+		found.map_or_else(|| self.standard_scan_zero(index, stride), Ok)
+	}
+
+	fn get_move_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, move_value: i32) -> RecompiledOps {
+		BfMemoryMemUnsafe::<C>::get_inline_move_ops(context_addr, memory_addr, move_ref_fn_addr, move_value)
+	}
+
+	fn get_offset_ref_ops(context_addr: [u8; 8], memory_addr: usize, get_offset_ref_fn_addr: usize, offset: i32) -> RecompiledOps {
+		BfMemoryMemUnsafe::<C>::get_inline_offset_ref_ops(context_addr, memory_addr, get_offset_ref_fn_addr, offset)
+	}
+
+	fn get_scan_zero_ops(context_addr: [u8; 8], memory_addr: usize, move_ref_fn_addr: usize, stride: i32) -> RecompiledOps {
+		// Scans of single steps search 16 bytes at a time using SSE2.
+		let size = C::SIZE as u8;
+		let (start_ops, next_block_ops, bit_scan_ops): (&[u8], &[u8], &[u8]) = match stride {
+			1 => (&[], &[0x48, 0x83, 0xc6, 0x10], &[0x0f, 0xbc, 0xff]), // add rsi, 16. bsf edi, edi
+			-1 => (&[0x48, 0x83, 0xee, 16 - size], &[0x48, 0x83, 0xee, 0x10], &[0x0f, 0xbd, 0xff]), // sub rsi, 16 - size. sub rsi, 16. bsr edi, edi
+			_ => return BfMemoryMemUnsafe::<C>::get_standard_scan_zero_ops(context_addr, memory_addr, move_ref_fn_addr, stride),
+		};
+		let compare_op = match C::SIZE {
+			1 => 0x74, // pcmpeqb
+			2 => 0x75, // pcmpeqw
+			_ => 0x76, // pcmpeqd
+		};
+		let mut scan_block = RecompiledOps::default();
+		// Put value of "dl" back into its position in bf_memory, the values passed over are unchanged.
+		scan_block.add_store_current::<C>();
+		scan_block.push_opcodes(&[0x48, 0x89, 0xc6]); // mov rsi, rax
+		scan_block.push_opcodes(start_ops); // Backwards scans load the 16 bytes ending at the current value.
+		scan_block.push_opcodes(&[0x66, 0x0f, 0xef, 0xc0]); // pxor xmm0, xmm0
+
+		let mut search_block = RecompiledOps::default();
+		search_block.push_opcodes(&[0xf3, 0x0f, 0x6f, 0x0e]); // movdqu xmm1, [rsi]
+		search_block.push_opcodes(&[0x66, 0x0f, compare_op, 0xc8]); // pcmpeq xmm1, xmm0
+		search_block.push_opcodes(&[0x66, 0x0f, 0xd7, 0xf9]); // pmovmskb edi, xmm1
+		search_block.push_opcodes(&[0x85, 0xff]); // test edi, edi
+		search_block.push_opcodes(&[0x75, next_block_ops.len() as u8 + 2]); // Jump not equal, out of the search loop.
+		search_block.push_opcodes(next_block_ops);
+		let jump_back = -(search_block.len() as i8) - 2;
+		search_block.push_opcodes(&[0xeb, jump_back as u8]); // Jump to the start of the search block.
+		scan_block.push_opcodes(&search_block);
+
+		// Byte index of the zero value closest to the current value.
+		scan_block.push_opcodes(bit_scan_ops);
+		if stride == -1 && size > 1 {
+			// bsr finds the last byte of the value.
+			scan_block.push_opcodes(&[0x83, 0xef, size - 1]); // sub edi, size - 1
+		}
+		scan_block.push_opcodes(&[0x48, 0x01, 0xfe]); // add rsi, rdi
+		// Increase the index by the distance moved.
+		scan_block.push_opcodes(&[0x48, 0x89, 0xf7]); // mov rdi, rsi
+		scan_block.push_opcodes(&[0x48, 0x29, 0xc7]); // sub rdi, rax
+		if size > 1 {
+			scan_block.push_opcodes(&[0x48, 0xc1, 0xff, size.trailing_zeros() as u8]); // sar rdi, log2(size). Bytes to values.
+		}
+		scan_block.push_opcodes(&[0x01, 0xf9]); // add ecx, edi
+		scan_block.push_opcodes(&[0x48, 0x89, 0xf0]); // mov rax, rsi
+		scan_block.push_opcodes(&[0x31, 0xd2]); // xor edx, edx
+
+		let mut recompiled_memory = RecompiledOps::default();
+		recompiled_memory.add_compare_current_zero::<C>();
+		recompiled_memory.push_opcodes(&[0x74, scan_block.len() as u8]); // Jump equal, over the scan.
+		recompiled_memory.push_opcodes(&scan_block);
+		recompiled_memory
+	}
+}
+
+pub(crate) mod bounded;
+pub(crate) mod guarded;
+
+pub use bounded::BfMemoryBounded;
+pub use guarded::BfMemoryMemGuarded;
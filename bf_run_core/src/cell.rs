@@ -0,0 +1,124 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+	fmt::{Debug, Display},
+	str::FromStr,
+};
+
+use crate::executors::OverflowPolicy;
+
+/// Integers with the wrapping arithmetic used by the brainfuck operations.
+pub trait WrappingInteger: Copy + Default + Eq + Debug + Display + FromStr + 'static {
+	const ZERO: Self;
+	const ONE: Self;
+	fn wrapping_add(self, rhs: Self) -> Self;
+	fn wrapping_sub(self, rhs: Self) -> Self;
+	fn wrapping_mul(self, rhs: Self) -> Self;
+	fn wrapping_neg(self) -> Self;
+	fn checked_add(self, rhs: Self) -> Option<Self>;
+}
+macro_rules! impl_wrapping_integer {
+	($($integer:ty),*) => {
+		$(
+			impl WrappingInteger for $integer {
+				const ONE: $integer = 1;
+				const ZERO: $integer = 0;
+
+				fn wrapping_add(self, rhs: $integer) -> $integer {
+					<$integer>::wrapping_add(self, rhs)
+				}
+
+				fn wrapping_sub(self, rhs: $integer) -> $integer {
+					<$integer>::wrapping_sub(self, rhs)
+				}
+
+				fn wrapping_mul(self, rhs: $integer) -> $integer {
+					<$integer>::wrapping_mul(self, rhs)
+				}
+
+				fn wrapping_neg(self) -> $integer {
+					<$integer>::wrapping_neg(self)
+				}
+
+				fn checked_add(self, rhs: $integer) -> Option<$integer> {
+					<$integer>::checked_add(self, rhs)
+				}
+			}
+		)*
+	};
+}
+impl_wrapping_integer!(u8, u16, u32, i8, i16, i32);
+
+/// The type of a single value in the brainfuck memory.
+/// Implemented for u8, u16 and u32, for 8, 16 and 32-bit cells.
+pub trait BfCell: WrappingInteger + From<u8> + Into<u32> {
+	/// Signed integer of the same width, used for the values that operations add to cells.
+	type Signed: WrappingInteger + Into<i32>;
+	/// Size of the cell in bytes.
+	const SIZE: usize;
+	const MAX: Self;
+
+	/// Reinterprets the bits of value as a cell.
+	fn from_signed(value: Self::Signed) -> Self;
+	/// The lowest bits of value, that fit in the cell.
+	fn truncate(value: i64) -> Self;
+
+	/// The lowest 8 bits of the cell, which is what '.' prints.
+	fn low_byte(self) -> u8 {
+		let value: u32 = self.into();
+		value as u8
+	}
+
+	fn wrapping_add_signed(self, value: Self::Signed) -> Self {
+		self.wrapping_add(Self::from_signed(value))
+	}
+
+	/// Adds value to the cell, handling a result outside of the range of the cell as specified by overflow.
+	/// Returns None if overflow is OverflowPolicy::Trapping and the result does not fit in the cell.
+	fn add_with_overflow(self, value: i64, overflow: OverflowPolicy) -> Option<Self> {
+		let max: u32 = Self::MAX.into();
+		let cell: u32 = self.into();
+		let result = cell as i64 + value;
+		match overflow {
+			OverflowPolicy::Wrapping => Some(Self::truncate(result)),
+			OverflowPolicy::Saturating => Some(Self::truncate(result.clamp(0, max as i64))),
+			OverflowPolicy::Trapping => (0..=max as i64).contains(&result).then(|| Self::truncate(result)),
+		}
+	}
+}
+macro_rules! impl_bf_cell {
+	($($cell:ty, $signed:ty);*) => {
+		$(
+			impl BfCell for $cell {
+				type Signed = $signed;
+
+				const MAX: $cell = <$cell>::MAX;
+				const SIZE: usize = std::mem::size_of::<$cell>();
+
+				fn from_signed(value: $signed) -> $cell {
+					value as $cell
+				}
+
+				fn truncate(value: i64) -> $cell {
+					value as $cell
+				}
+			}
+		)*
+	};
+}
+impl_bf_cell!(u8, i8; u16, i16; u32, i32);
@@ -0,0 +1,116 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::CompilerOptions;
+use crate::{
+	cell::BfCell,
+	executors::{operations::*, EofPolicy, OverflowPolicy},
+};
+
+const DEFAULT_MEMORY_SIZE: usize = 65535;
+
+/// Translates brainfuck code to a portable C program, with cells of type C.
+/// Like ElfCompiler, the pointer starts at the center of the memory, and the memory is not bounds checked.
+pub struct CTranspiler<C: BfCell> {
+	operations: Operations<C>,
+	options:    CompilerOptions,
+}
+impl<C: BfCell> CTranspiler<C> {
+	pub fn new(code: &str, options: CompilerOptions) -> Result<CTranspiler<C>, ParseError> {
+		let mut operations = Operations::conv_string_to_operations(code, false)?;
+		if options.enable_optimizations {
+			operations.optimize(OverflowPolicy::Wrapping);
+		}
+		Ok(CTranspiler { operations, options })
+	}
+
+	/// Creates the source code of the C program.
+	/// The cells are unsigned integers, so the arithmetic wraps like OverflowPolicy::Wrapping.
+	pub fn transpile(&self) -> String {
+		let memory_size = self.options.memory_size.unwrap_or(DEFAULT_MEMORY_SIZE);
+		let mut source = String::new();
+		source.push_str("#include <stdint.h>\n#include <stdio.h>\n#include <stdlib.h>\n\n");
+		source.push_str(&format!("#define MEMORY_SIZE {}\n\n", memory_size));
+		source.push_str(&format!("typedef uint{}_t cell;\n\n", C::SIZE * 8));
+		source.push_str("static cell memory[MEMORY_SIZE];\n\n");
+		source.push_str("int main(void) {\n");
+		source.push_str("\tcell *p = memory + MEMORY_SIZE / 2;\n");
+		CTranspiler::convert_operations(&self.operations, self.options.eof_policy, 1, &mut source);
+		source.push_str("\treturn 0;\n}\n");
+		source
+	}
+
+	/// Appends a statement for each operation to source, indented by depth tabs.
+	fn convert_operations(operations: &[Operation<C>], eof_policy: EofPolicy, depth: usize, source: &mut String) {
+		let indent = "\t".repeat(depth);
+		for operation in operations {
+			let statement = match operation {
+				Operation::Mod { offset, value, .. } => {
+					let value: i32 = (*value).into();
+					match value < 0 {
+						true => format!("{} -= {};", cell_ref(*offset), -(value as i64)),
+						false => format!("{} += {};", cell_ref(*offset), value),
+					}
+				},
+				Operation::Move(move_value, _) => format!("p += {};", move_value),
+				Operation::Loop(operations, _) => {
+					source.push_str(&format!("{}while (*p) {{\n", indent));
+					CTranspiler::convert_operations(operations, eof_policy, depth + 1, source);
+					source.push_str(&format!("{}}}\n", indent));
+					continue;
+				},
+				Operation::Set { offset, value, .. } => format!("{} = {};", cell_ref(*offset), Into::<u32>::into(*value)),
+				Operation::GetInput(_) => CTranspiler::<C>::read_statement(eof_policy),
+				Operation::Print { offset, .. } => format!("putchar((unsigned char){});", cell_ref(*offset)),
+				Operation::MulAdd { offset, factor, .. } => {
+					let factor: i32 = (*factor).into();
+					// Smaller cells are promoted to int, where the product can not overflow.
+					// 32 bit cells are multiplied as unsigned integers, which wrap instead.
+					match C::SIZE {
+						4 => format!("{} += *p * {}u;", cell_ref(*offset), factor as u32),
+						_ => format!("{} += *p * {};", cell_ref(*offset), factor),
+					}
+				},
+				Operation::ScanZero(stride, _) => format!("while (*p) p += {};", stride),
+				// '#' is not parsed for transpiled programs.
+				Operation::DebugDump(_) => continue,
+			};
+			source.push_str(&format!("{}{}\n", indent, statement));
+		}
+	}
+
+	/// Reads a byte into the current cell, handling the end of the input as specified by eof_policy.
+	fn read_statement(eof_policy: EofPolicy) -> String {
+		let end_of_input = match eof_policy {
+			EofPolicy::Unchanged => "",
+			EofPolicy::Zero => " else *p = 0;",
+			EofPolicy::MinusOne => " else *p = (cell)-1;",
+			EofPolicy::Error => {
+				" else { fflush(stdout); fputs(\"\\nError: Tried to read past the end of the input\\n\", stderr); exit(1); }"
+			},
+		};
+		format!("{{ int input = getchar(); if (input != EOF) *p = (cell)input;{} }}", end_of_input)
+	}
+}
+
+/// The C expression for the cell at offset from the pointer.
+fn cell_ref(offset: i32) -> String {
+	match offset {
+		0 => "*p".to_string(),
+		_ => format!("p[{}]", offset),
+	}
+}
@@ -0,0 +1,301 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::CompilerOptions;
+use crate::executors::{bf_recompiler::RecompiledOps, operations::*, EofPolicy, OverflowPolicy, OUTPUT_BUFFER_SIZE};
+
+/// Virtual address that the executable is loaded at.
+const LOAD_ADDR: u64 = 0x40_0000;
+const PAGE_SIZE: u64 = 4096;
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+/// The machine code is placed right after the ELF header and the two program headers.
+const CODE_OFFSET: usize = ELF_HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE;
+const DEFAULT_MEMORY_SIZE: usize = 65535;
+
+/// Offsets into the .bss segment, that "r15" points to.
+const OUTPUT_BUFFER_OFFSET: i32 = 0;
+const MEMORY_OFFSET: i32 = OUTPUT_BUFFER_SIZE as i32;
+
+const SYS_READ: u32 = 0;
+const SYS_WRITE: u32 = 1;
+const SYS_EXIT: u32 = 60;
+
+const END_OF_INPUT_MESSAGE: &[u8] = b"\nError: Tried to read past the end of the input\n";
+const IO_ERROR_MESSAGE: &[u8] = b"\nError: Input/output error\n";
+
+/// Positions of the subroutines in the machine code.
+struct Subroutines {
+	/// Writes the output buffer to stdout.
+	flush: usize,
+	/// Reads a byte from stdin into the current value.
+	read:  usize,
+}
+
+/// Compiles brainfuck code to a standalone x86-64 Linux executable,
+/// that uses system calls instead of calling into rust code.
+/// Like BfMemoryMemUnsafe, the memory of the compiled program is not bounds checked.
+pub struct ElfCompiler {
+	operations: Operations<u8>,
+	options:    CompilerOptions,
+}
+impl ElfCompiler {
+	pub fn new(code: &str, options: CompilerOptions) -> Result<ElfCompiler, ParseError> {
+		let mut operations = Operations::conv_string_to_operations(code, false)?;
+		if options.enable_optimizations {
+			operations.optimize(OverflowPolicy::Wrapping);
+		}
+		Ok(ElfCompiler { operations, options })
+	}
+
+	/// Creates the contents of the executable file.
+	///
+	/// The machine code is position independent, all addresses are relative to "rip",
+	/// or to the .bss segment which "r15" points to.
+	/// "rbx" points to the current value, "r13" holds the length of the output buffer.
+	pub fn compile(&self) -> Vec<u8> {
+		let memory_size = self.options.memory_size.unwrap_or(DEFAULT_MEMORY_SIZE);
+		let mut code = RecompiledOps::default();
+
+		code.push_opcodes(&[0x4c, 0x8d, 0x3d]); // lea r15, [rip + next argument]
+		let bss_displacement_pos = code.len();
+		code.push_opcodes(&0i32.to_ne_bytes()); // argument for lea, set when the size of the code is known.
+		code.push_opcodes(&[0x45, 0x31, 0xed]); // xor r13d, r13d
+		code.push_opcodes(&[0x49, 0x8d, 0x9f]); // lea rbx, [r15 + next argument]
+		code.push_opcodes(&(MEMORY_OFFSET + (memory_size / 2) as i32).to_ne_bytes()); // argument for lea, center of the memory.
+
+		code.push(0xe9); // Jump, over the subroutines.
+		let jump_pos = code.len();
+		code.push_opcodes(&0i32.to_ne_bytes());
+		let subroutines = ElfCompiler::add_subroutines(self.options.eof_policy, &mut code);
+		ElfCompiler::patch_jump(&mut code, jump_pos);
+
+		ElfCompiler::convert_to_machine_code(&self.operations, &subroutines, &mut code);
+		ElfCompiler::add_call(&mut code, subroutines.flush);
+		ElfCompiler::add_exit(&mut code, 0);
+
+		let file_size = (CODE_OFFSET + code.len()) as u64;
+		let bss_addr = (LOAD_ADDR + file_size).next_multiple_of(PAGE_SIZE);
+		// The lea instruction ends 7 bytes after the start of the code.
+		let bss_displacement = (bss_addr - (LOAD_ADDR + CODE_OFFSET as u64 + 7)) as i32;
+		code[bss_displacement_pos..bss_displacement_pos + 4].copy_from_slice(&bss_displacement.to_ne_bytes());
+
+		let bss_size = (MEMORY_OFFSET as usize + memory_size) as u64;
+		ElfCompiler::create_elf(&code, bss_addr, bss_size)
+	}
+
+	fn convert_to_machine_code(operations: &[Operation<u8>], subroutines: &Subroutines, code: &mut RecompiledOps) {
+		operations.iter().for_each(|operation| match operation {
+			Operation::Mod { offset, value, .. } => {
+				code.push_opcodes(&[0x80, 0x83]); // add byte [rbx + next argument]
+				code.push_opcodes(&offset.to_ne_bytes()); // argument for add.
+				code.push(*value as u8); // Value to add.
+			},
+			Operation::Move(move_value, _) => {
+				code.push_opcodes(&[0x48, 0x81, 0xc3]); // add rbx
+				code.push_opcodes(&move_value.to_ne_bytes()); // argument for add rbx.
+			},
+			Operation::Loop(operations, _) => {
+				let loop_start = code.len();
+				code.push_opcodes(&[0x80, 0x3b, 0x00]); // cmp byte [rbx], 0
+				code.push_opcodes(&[0x0f, 0x84]); // Jump equal, past the end of the loop.
+				let jump_pos = code.len();
+				code.push_opcodes(&0i32.to_ne_bytes());
+
+				ElfCompiler::convert_to_machine_code(operations, subroutines, code);
+
+				code.push(0xe9); // Jump, back to the comparison.
+				code.push_opcodes(&(loop_start as i32 - (code.len() + 4) as i32).to_ne_bytes());
+				ElfCompiler::patch_jump(code, jump_pos);
+			},
+			Operation::Set { offset, value, .. } => {
+				code.push_opcodes(&[0xc6, 0x83]); // mov byte [rbx + next argument]
+				code.push_opcodes(&offset.to_ne_bytes()); // argument for mov.
+				code.push(*value); // Value to set.
+			},
+			Operation::GetInput(_) => ElfCompiler::add_call(code, subroutines.read),
+			Operation::Print { offset, .. } => {
+				code.push_opcodes(&[0x8a, 0x83]); // mov al, [rbx + next argument]
+				code.push_opcodes(&offset.to_ne_bytes()); // argument for mov.
+				code.push_opcodes(&[0x43, 0x88, 0x84, 0x2f]); // mov [r15 + r13 + next argument], al
+				code.push_opcodes(&OUTPUT_BUFFER_OFFSET.to_ne_bytes()); // argument for mov.
+				code.push_opcodes(&[0x49, 0xff, 0xc5]); // inc r13
+				code.push_opcodes(&[0x49, 0x81, 0xfd]); // cmp r13
+				code.push_opcodes(&(OUTPUT_BUFFER_SIZE as u32).to_ne_bytes()); // argument for cmp r13.
+				code.push_opcodes(&[0x75, 0x05]); // Jump not equal, over the call.
+				ElfCompiler::add_call(code, subroutines.flush);
+			},
+			Operation::MulAdd { offset, factor, .. } => {
+				code.push_opcodes(&[0x0f, 0xb6, 0x03]); // movzx eax, byte [rbx]
+				code.push_opcodes(&[0x6b, 0xc0]); // imul eax, eax
+				code.push(*factor as u8); // argument for imul.
+				code.push_opcodes(&[0x00, 0x83]); // add [rbx + next argument], al
+				code.push_opcodes(&offset.to_ne_bytes()); // argument for add.
+			},
+			Operation::ScanZero(stride, _) => {
+				code.push_opcodes(&[0x80, 0x3b, 0x00]); // cmp byte [rbx], 0
+				code.push_opcodes(&[0x74, 0x09]); // Jump equal, out of the scan.
+				code.push_opcodes(&[0x48, 0x81, 0xc3]); // add rbx
+				code.push_opcodes(&stride.to_ne_bytes()); // argument for add rbx.
+				code.push_opcodes(&[0xeb, 0xf2]); // Jump, back to the comparison.
+			},
+			// '#' is not parsed for compiled programs.
+			Operation::DebugDump(_) => (),
+		});
+	}
+
+	/// Adds the subroutines for input and output, which are called from the converted operations.
+	fn add_subroutines(eof_policy: EofPolicy, code: &mut RecompiledOps) -> Subroutines {
+		let io_error = code.len();
+		ElfCompiler::add_error_exit(code, IO_ERROR_MESSAGE);
+		let end_of_input = code.len();
+		ElfCompiler::add_error_exit(code, END_OF_INPUT_MESSAGE);
+
+		let flush = code.len();
+		code.push_opcodes(&[0x49, 0x8d, 0xb7]); // lea rsi, [r15 + next argument]
+		code.push_opcodes(&OUTPUT_BUFFER_OFFSET.to_ne_bytes()); // argument for lea.
+		// Write until the buffer is empty, as write may only write a part of it.
+		let flush_loop = code.len();
+		code.push_opcodes(&[0x4d, 0x85, 0xed]); // test r13, r13
+		let write_start = code.len() + 2;
+		let mut write_ops = RecompiledOps::default();
+		write_ops.push(0xb8); // mov eax
+		write_ops.push_opcodes(&SYS_WRITE.to_ne_bytes()); // argument for mov eax.
+		write_ops.push(0xbf); // mov edi
+		write_ops.push_opcodes(&1u32.to_ne_bytes()); // argument for mov edi, stdout.
+		write_ops.push_opcodes(&[0x4c, 0x89, 0xea]); // mov rdx, r13
+		write_ops.push_opcodes(&[0x0f, 0x05]); // syscall
+		write_ops.push_opcodes(&[0x48, 0x85, 0xc0]); // test rax, rax
+		write_ops.push_opcodes(&[0x0f, 0x8e]); // Jump less or equal, to the io error.
+		write_ops.push_opcodes(&(io_error as i32 - (write_start + write_ops.len() + 4) as i32).to_ne_bytes());
+		write_ops.push_opcodes(&[0x48, 0x01, 0xc6]); // add rsi, rax
+		write_ops.push_opcodes(&[0x49, 0x29, 0xc5]); // sub r13, rax
+		write_ops.push(0xeb); // Jump, back to the test.
+		let jump_back = flush_loop as i32 - (write_start + write_ops.len() + 1) as i32;
+		write_ops.push(jump_back as u8);
+		code.push_opcodes(&[0x74, write_ops.len() as u8]); // Jump equal, over the write to the return.
+		code.push_opcodes(&write_ops);
+		code.push(0xc3); // return
+
+		let read = code.len();
+		// Flush the output first, so prompts are shown before waiting for input.
+		ElfCompiler::add_call(code, flush);
+		code.push(0xb8); // mov eax
+		code.push_opcodes(&SYS_READ.to_ne_bytes()); // argument for mov eax.
+		code.push_opcodes(&[0x31, 0xff]); // xor edi, edi. stdin.
+		code.push_opcodes(&[0x48, 0x89, 0xde]); // mov rsi, rbx
+		code.push(0xba); // mov edx
+		code.push_opcodes(&1u32.to_ne_bytes()); // argument for mov edx.
+		code.push_opcodes(&[0x0f, 0x05]); // syscall
+		code.push_opcodes(&[0x48, 0x85, 0xc0]); // test rax, rax
+		// Read returned 0 at the end of the input.
+		let eof_start = code.len() + 2 + 6;
+		let mut eof_ops = RecompiledOps::default();
+		match eof_policy {
+			EofPolicy::Unchanged => (),
+			EofPolicy::Zero => eof_ops.push_opcodes(&[0xc6, 0x03, 0x00]), // mov byte [rbx], 0
+			EofPolicy::MinusOne => eof_ops.push_opcodes(&[0xc6, 0x03, 0xff]), // mov byte [rbx], 255
+			EofPolicy::Error => {
+				eof_ops.push(0xe9); // Jump, to the end of input error.
+				eof_ops.push_opcodes(&(end_of_input as i32 - (eof_start + 5) as i32).to_ne_bytes());
+			},
+		}
+		code.push_opcodes(&[0x7f, eof_ops.len() as u8 + 6]); // Jump greater, to the return.
+		code.push_opcodes(&[0x0f, 0x8c]); // Jump less, to the io error.
+		code.push_opcodes(&(io_error as i32 - (code.len() + 4) as i32).to_ne_bytes());
+		code.push_opcodes(&eof_ops);
+		code.push(0xc3); // return
+
+		Subroutines { flush, read }
+	}
+
+	/// Writes message to stderr, and exits with status 1.
+	fn add_error_exit(code: &mut RecompiledOps, message: &[u8]) {
+		let mut write_ops = RecompiledOps::default();
+		write_ops.push(0xba); // mov edx
+		write_ops.push_opcodes(&(message.len() as u32).to_ne_bytes()); // argument for mov edx.
+		write_ops.push(0xbf); // mov edi
+		write_ops.push_opcodes(&2u32.to_ne_bytes()); // argument for mov edi, stderr.
+		write_ops.push(0xb8); // mov eax
+		write_ops.push_opcodes(&SYS_WRITE.to_ne_bytes()); // argument for mov eax.
+		write_ops.push_opcodes(&[0x0f, 0x05]); // syscall
+		ElfCompiler::add_exit(&mut write_ops, 1);
+
+		code.push_opcodes(&[0x48, 0x8d, 0x35]); // lea rsi, [rip + next argument]
+		code.push_opcodes(&(write_ops.len() as i32).to_ne_bytes()); // argument for lea, the message is after the exit.
+		code.push_opcodes(&write_ops);
+		code.push_opcodes(message);
+	}
+
+	fn add_exit(code: &mut RecompiledOps, status: u32) {
+		code.push(0xbf); // mov edi
+		code.push_opcodes(&status.to_ne_bytes()); // argument for mov edi.
+		code.push(0xb8); // mov eax
+		code.push_opcodes(&SYS_EXIT.to_ne_bytes()); // argument for mov eax.
+		code.push_opcodes(&[0x0f, 0x05]); // syscall
+	}
+
+	fn add_call(code: &mut RecompiledOps, target: usize) {
+		code.push(0xe8); // call
+		code.push_opcodes(&(target as i32 - (code.len() + 4) as i32).to_ne_bytes());
+	}
+
+	/// Sets the 32 bit jump argument at jump_pos, to jump to the end of the code.
+	fn patch_jump(code: &mut RecompiledOps, jump_pos: usize) {
+		let distance = (code.len() - (jump_pos + 4)) as i32;
+		code[jump_pos..jump_pos + 4].copy_from_slice(&distance.to_ne_bytes());
+	}
+
+	/// Wraps the machine code in an ELF64 header, with a segment for the code and a segment for the .bss.
+	fn create_elf(code: &[u8], bss_addr: u64, bss_size: u64) -> Vec<u8> {
+		let file_size = (CODE_OFFSET + code.len()) as u64;
+		let mut elf = Vec::with_capacity(file_size as usize);
+		// ELF header.
+		elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]); // 64 bit, little endian, version 1, System V ABI.
+		elf.extend_from_slice(&[0; 8]); // Padding of the identification.
+		elf.extend_from_slice(&2u16.to_le_bytes()); // Type, executable.
+		elf.extend_from_slice(&0x3eu16.to_le_bytes()); // Machine, x86-64.
+		elf.extend_from_slice(&1u32.to_le_bytes()); // Version.
+		elf.extend_from_slice(&(LOAD_ADDR + CODE_OFFSET as u64).to_le_bytes()); // Entry point.
+		elf.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // Program header offset.
+		elf.extend_from_slice(&0u64.to_le_bytes()); // Section header offset, there are no sections.
+		elf.extend_from_slice(&0u32.to_le_bytes()); // Flags.
+		elf.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes()); // ELF header size.
+		elf.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes()); // Program header size.
+		elf.extend_from_slice(&2u16.to_le_bytes()); // Number of program headers.
+		elf.extend_from_slice(&[0; 6]); // Section header size, count and string table index.
+
+		// Code segment, readable and executable.
+		ElfCompiler::add_program_header(&mut elf, 5, 0, LOAD_ADDR, file_size, file_size);
+		// .bss segment, readable and writable, zeroed by the kernel.
+		ElfCompiler::add_program_header(&mut elf, 6, 0, bss_addr, 0, bss_size);
+
+		elf.extend_from_slice(code);
+		elf
+	}
+
+	fn add_program_header(elf: &mut Vec<u8>, flags: u32, offset: u64, addr: u64, file_size: u64, memory_size: u64) {
+		elf.extend_from_slice(&1u32.to_le_bytes()); // Type, loadable segment.
+		elf.extend_from_slice(&flags.to_le_bytes());
+		elf.extend_from_slice(&offset.to_le_bytes());
+		elf.extend_from_slice(&addr.to_le_bytes()); // Virtual address.
+		elf.extend_from_slice(&addr.to_le_bytes()); // Physical address.
+		elf.extend_from_slice(&file_size.to_le_bytes());
+		elf.extend_from_slice(&memory_size.to_le_bytes());
+		elf.extend_from_slice(&PAGE_SIZE.to_le_bytes()); // Alignment.
+	}
+}
@@ -0,0 +1,237 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::CompilerOptions;
+use crate::{
+	cell::BfCell,
+	executors::{operations::*, EofPolicy, OverflowPolicy},
+};
+
+const DEFAULT_MEMORY_SIZE: usize = 65535;
+const END_OF_INPUT_MESSAGE: &str = "\\0AError: Tried to read past the end of the input\\0A";
+/// Length of END_OF_INPUT_MESSAGE, with each escaped newline counted as one byte.
+const END_OF_INPUT_MESSAGE_LEN: usize = 48;
+
+/// Translates brainfuck code to textual LLVM IR, with cells of type C.
+/// The memory is a global array, and the pointer is kept in an alloca, which LLVM's mem2reg promotes to registers.
+/// Like ElfCompiler, the pointer starts at the center of the memory, and the memory is not bounds checked.
+pub struct LlvmTranspiler<C: BfCell> {
+	operations: Operations<C>,
+	options:    CompilerOptions,
+}
+impl<C: BfCell> LlvmTranspiler<C> {
+	pub fn new(code: &str, options: CompilerOptions) -> Result<LlvmTranspiler<C>, ParseError> {
+		let mut operations = Operations::conv_string_to_operations(code, false)?;
+		if options.enable_optimizations {
+			operations.optimize(OverflowPolicy::Wrapping);
+		}
+		Ok(LlvmTranspiler { operations, options })
+	}
+
+	/// Creates the LLVM IR module, with a main function that runs the program using getchar and putchar.
+	/// The IR uses opaque pointers, so it needs LLVM 15 or later, or the "-opaque-pointers" option in earlier versions.
+	pub fn transpile(&self) -> String {
+		let memory_size = self.options.memory_size.unwrap_or(DEFAULT_MEMORY_SIZE);
+		let cell_type = format!("i{}", C::SIZE * 8);
+		let mut body = LlvmBody { cell_type: cell_type.clone(), eof_policy: self.options.eof_policy, ..Default::default() };
+		body.add_operations(&self.operations);
+
+		let mut source = String::new();
+		source.push_str(&format!("@memory = internal global [{} x {}] zeroinitializer\n", memory_size, cell_type));
+		if self.options.eof_policy == EofPolicy::Error {
+			source.push_str(&format!(
+				"@end_of_input_message = private constant [{} x i8] c\"{}\"\n",
+				END_OF_INPUT_MESSAGE_LEN, END_OF_INPUT_MESSAGE
+			));
+		}
+		source.push_str("\ndeclare i32 @getchar()\ndeclare i32 @putchar(i32)\n");
+		if self.options.eof_policy == EofPolicy::Error {
+			source.push_str("declare i32 @fflush(ptr)\ndeclare i64 @write(i32, ptr, i64)\ndeclare void @exit(i32)\n");
+		}
+		source.push_str("\ndefine i32 @main() {\nentry:\n");
+		source.push_str("\t%p = alloca ptr\n");
+		source.push_str(&format!(
+			"\tstore ptr getelementptr ([{} x {}], ptr @memory, i64 0, i64 {}), ptr %p\n",
+			memory_size,
+			cell_type,
+			memory_size / 2
+		));
+		source.push_str(&body.source);
+		source.push_str("\tret i32 0\n");
+		if self.options.eof_policy == EofPolicy::Error {
+			source.push_str("\nend_of_input:\n");
+			source.push_str("\tcall i32 @fflush(ptr null)\n");
+			source.push_str(&format!("\tcall i64 @write(i32 2, ptr @end_of_input_message, i64 {})\n", END_OF_INPUT_MESSAGE_LEN));
+			source.push_str("\tcall void @exit(i32 1)\n\tunreachable\n");
+		}
+		source.push_str("}\n");
+		source
+	}
+}
+
+/// The instructions of the main function, with the counters for naming values and labels.
+#[derive(Default)]
+struct LlvmBody {
+	source:     String,
+	cell_type:  String,
+	eof_policy: EofPolicy,
+	next_value: usize,
+	next_label: usize,
+}
+impl LlvmBody {
+	fn add_operations<C: BfCell>(&mut self, operations: &[Operation<C>]) {
+		for operation in operations {
+			match operation {
+				Operation::Mod { offset, value, .. } => {
+					let address = self.add_address(*offset);
+					let old = self.add_load(&address);
+					let new = self.add_value(&format!("add {} {}, {}", self.cell_type, old, Into::<i32>::into(*value)));
+					self.add_store(&new, &address);
+				},
+				Operation::Move(move_value, _) => self.add_move(*move_value),
+				Operation::Loop(operations, _) => {
+					let label = self.add_loop_start();
+					self.add_operations(operations);
+					self.add_loop_end(label);
+				},
+				Operation::Set { offset, value, .. } => {
+					let address = self.add_address(*offset);
+					self.add_store(&signed::<C>(*value).to_string(), &address);
+				},
+				Operation::GetInput(_) => self.add_get_input::<C>(),
+				Operation::Print { offset, .. } => {
+					let address = self.add_address(*offset);
+					let value = self.add_load(&address);
+					let character = match C::SIZE {
+						4 => value,
+						_ => self.add_value(&format!("zext {} {} to i32", self.cell_type, value)),
+					};
+					self.add_instruction(&format!("call i32 @putchar(i32 {})", character));
+				},
+				Operation::MulAdd { offset, factor, .. } => {
+					let current_address = self.add_address(0);
+					let current = self.add_load(&current_address);
+					let product = self.add_value(&format!("mul {} {}, {}", self.cell_type, current, Into::<i32>::into(*factor)));
+					let address = self.add_address(*offset);
+					let old = self.add_load(&address);
+					let new = self.add_value(&format!("add {} {}, {}", self.cell_type, old, product));
+					self.add_store(&new, &address);
+				},
+				Operation::ScanZero(stride, _) => {
+					let label = self.add_loop_start();
+					self.add_move(*stride);
+					self.add_loop_end(label);
+				},
+				// '#' is not parsed for transpiled programs.
+				Operation::DebugDump(_) => (),
+			}
+		}
+	}
+
+	/// Reads a character into the current cell, getchar returns a negative value at the end of the input.
+	fn add_get_input<C: BfCell>(&mut self) {
+		let character = self.add_value("call i32 @getchar()");
+		let end_of_input = self.add_value(&format!("icmp slt i32 {}, 0", character));
+		let input = match C::SIZE {
+			4 => character,
+			_ => self.add_value(&format!("trunc i32 {} to {}", character, self.cell_type)),
+		};
+		let address = self.add_address(0);
+		let value = match self.eof_policy {
+			EofPolicy::Unchanged => {
+				let old = self.add_load(&address);
+				self.add_value(&format!("select i1 {}, {} {}, {} {}", end_of_input, self.cell_type, old, self.cell_type, input))
+			},
+			EofPolicy::Zero | EofPolicy::MinusOne => {
+				let replacement = if self.eof_policy == EofPolicy::Zero { 0 } else { -1 };
+				self.add_value(&format!(
+					"select i1 {}, {} {}, {} {}",
+					end_of_input, self.cell_type, replacement, self.cell_type, input
+				))
+			},
+			EofPolicy::Error => {
+				let label = self.next_label;
+				self.next_label += 1;
+				self.add_instruction(&format!("br i1 {}, label %end_of_input, label %read{}", end_of_input, label));
+				self.source.push_str(&format!("read{}:\n", label));
+				input
+			},
+		};
+		self.add_store(&value, &address);
+	}
+
+	fn add_instruction(&mut self, instruction: &str) {
+		self.source.push_str(&format!("\t{}\n", instruction));
+	}
+
+	/// Adds an instruction that has a result, and returns the name of the result.
+	fn add_value(&mut self, instruction: &str) -> String {
+		let name = format!("%v{}", self.next_value);
+		self.next_value += 1;
+		self.add_instruction(&format!("{} = {}", name, instruction));
+		name
+	}
+
+	/// Returns the name of the address of the cell at offset from the pointer.
+	fn add_address(&mut self, offset: i32) -> String {
+		let pointer = self.add_value("load ptr, ptr %p");
+		match offset {
+			0 => pointer,
+			_ => self.add_value(&format!("getelementptr {}, ptr {}, i64 {}", self.cell_type, pointer, offset)),
+		}
+	}
+
+	fn add_load(&mut self, address: &str) -> String {
+		self.add_value(&format!("load {}, ptr {}", self.cell_type, address))
+	}
+
+	fn add_store(&mut self, value: &str, address: &str) {
+		self.add_instruction(&format!("store {} {}, ptr {}", self.cell_type, value, address));
+	}
+
+	fn add_move(&mut self, move_value: i32) {
+		let address = self.add_address(move_value);
+		self.add_instruction(&format!("store ptr {}, ptr %p", address));
+	}
+
+	/// Adds the blocks that check the current value, and starts the body of the loop.
+	/// Returns the number in the labels of the loop.
+	fn add_loop_start(&mut self) -> usize {
+		let label = self.next_label;
+		self.next_label += 1;
+		self.add_instruction(&format!("br label %loop{}", label));
+		self.source.push_str(&format!("loop{}:\n", label));
+		let current_address = self.add_address(0);
+		let current = self.add_load(&current_address);
+		let not_zero = self.add_value(&format!("icmp ne {} {}, 0", self.cell_type, current));
+		self.add_instruction(&format!("br i1 {}, label %body{}, label %end{}", not_zero, label, label));
+		self.source.push_str(&format!("body{}:\n", label));
+		label
+	}
+
+	fn add_loop_end(&mut self, label: usize) {
+		self.add_instruction(&format!("br label %loop{}", label));
+		self.source.push_str(&format!("end{}:\n", label));
+	}
+}
+
+/// The value of the cell as a signed integer, as LLVM IR writes integer constants.
+fn signed<C: BfCell>(value: C) -> i64 {
+	let bits = C::SIZE as u32 * 8;
+	let value: u32 = value.into();
+	((value as i64) << (64 - bits)) >> (64 - bits)
+}
@@ -0,0 +1,46 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::executors::EofPolicy;
+
+/// Settings for the compilers and transpilers, that translate brainfuck programs instead of running them.
+#[derive(Debug, Clone)]
+pub struct CompilerOptions {
+	/// Run the optimization passes on the operations before compiling them.
+	pub enable_optimizations: bool,
+	/// Length of the memory in the compiled program.
+	pub memory_size:          Option<usize>,
+	/// What reading past the end of the input does in the compiled program.
+	pub eof_policy:           EofPolicy,
+}
+impl Default for CompilerOptions {
+	fn default() -> CompilerOptions {
+		CompilerOptions { enable_optimizations: true, memory_size: None, eof_policy: EofPolicy::default() }
+	}
+}
+
+pub mod c;
+pub mod elf;
+pub mod llvm;
+pub mod rust;
+pub mod wasm;
+
+pub use c::CTranspiler;
+pub use elf::ElfCompiler;
+pub use llvm::LlvmTranspiler;
+pub use rust::RustTranspiler;
+pub use wasm::WasmCompiler;
@@ -0,0 +1,173 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::CompilerOptions;
+use crate::{
+	cell::BfCell,
+	executors::{operations::*, EofPolicy, OverflowPolicy},
+};
+
+const DEFAULT_MEMORY_SIZE: usize = 65535;
+
+/// Translates brainfuck code to Rust source code, with cells of type C.
+/// The pointer starts at the center of the memory, leaving it panics like other out of bounds indexing.
+pub struct RustTranspiler<C: BfCell> {
+	operations: Operations<C>,
+	options:    CompilerOptions,
+}
+impl<C: BfCell> RustTranspiler<C> {
+	pub fn new(code: &str, options: CompilerOptions) -> Result<RustTranspiler<C>, ParseError> {
+		let mut operations = Operations::conv_string_to_operations(code, false)?;
+		if options.enable_optimizations {
+			operations.optimize(OverflowPolicy::Wrapping);
+		}
+		Ok(RustTranspiler { operations, options })
+	}
+
+	/// Creates a self-contained main.rs, that runs the program with stdin and stdout.
+	pub fn transpile(&self) -> String {
+		let mut source = self.transpile_function();
+		source.push_str("\nfn main() {\n");
+		source.push_str("\tlet mut output = std::io::BufWriter::new(std::io::stdout().lock());\n");
+		source.push_str("\tif let Err(err) = run(&mut std::io::stdin().lock(), &mut output) {\n");
+		source.push_str("\t\teprintln!(\"\\nError: {}\", err);\n");
+		source.push_str("\t\tstd::process::exit(1);\n");
+		source.push_str("\t}\n}\n");
+		source
+	}
+
+	/// Creates the function "pub fn run(input: &mut impl Read, output: &mut impl Write) -> std::io::Result<()>",
+	/// which runs the program, for including in other Rust code, like the output of a build script.
+	/// The output is flushed before reading input, and when the program finishes.
+	pub fn transpile_function(&self) -> String {
+		// A move at the end of the program has no effect, and would be reported as an unused assignment.
+		let operations = match self.operations.split_last() {
+			Some((Operation::Move(..), operations)) => operations,
+			_ => &self.operations,
+		};
+		let mut body = RustBody::default();
+		body.add_operations(operations, self.options.eof_policy, 1);
+		let memory_size = self.options.memory_size.unwrap_or(DEFAULT_MEMORY_SIZE);
+		let cell_type = format!("u{}", C::SIZE * 8);
+
+		let mut source = String::new();
+		source.push_str(&format!(
+			"pub fn run({}input: &mut impl std::io::Read, output: &mut impl std::io::Write) -> std::io::Result<()> {{\n",
+			if body.reads_input { "" } else { "_" }
+		));
+		source.push_str(&format!("\tconst MEMORY_SIZE: usize = {};\n", memory_size));
+		source.push_str(&format!(
+			"\tlet {}memory: Vec<{}> = vec![0; MEMORY_SIZE];\n",
+			if body.modifies_memory { "mut " } else { "" },
+			cell_type
+		));
+		source.push_str(&format!("\tlet {}p = MEMORY_SIZE / 2;\n", if body.moves_pointer { "mut " } else { "" }));
+		source.push_str(&body.source);
+		source.push_str("\toutput.flush()\n}\n");
+		source
+	}
+}
+
+/// The statements of the run function, and what they use of the state declared before them.
+#[derive(Default)]
+struct RustBody {
+	source:          String,
+	reads_input:     bool,
+	modifies_memory: bool,
+	moves_pointer:   bool,
+}
+impl RustBody {
+	/// Appends a statement for each operation, indented by depth tabs.
+	fn add_operations<C: BfCell>(&mut self, operations: &[Operation<C>], eof_policy: EofPolicy, depth: usize) {
+		let indent = "\t".repeat(depth);
+		for operation in operations {
+			let statement = match operation {
+				Operation::Mod { offset, value, .. } => {
+					self.modifies_memory = true;
+					let value: i32 = (*value).into();
+					let target = cell_ref(*offset);
+					match value < 0 {
+						true => format!("{} = {}.wrapping_sub({});", target, target, -(value as i64)),
+						false => format!("{} = {}.wrapping_add({});", target, target, value),
+					}
+				},
+				Operation::Move(move_value, _) => {
+					self.moves_pointer = true;
+					match *move_value < 0 {
+						true => format!("p -= {};", -(*move_value as i64)),
+						false => format!("p += {};", move_value),
+					}
+				},
+				Operation::Loop(operations, _) => {
+					self.source.push_str(&format!("{}while memory[p] != 0 {{\n", indent));
+					self.add_operations(operations, eof_policy, depth + 1);
+					self.source.push_str(&format!("{}}}\n", indent));
+					continue;
+				},
+				Operation::Set { offset, value, .. } => {
+					self.modifies_memory = true;
+					format!("{} = {};", cell_ref(*offset), Into::<u32>::into(*value))
+				},
+				Operation::GetInput(_) => {
+					self.reads_input = true;
+					self.modifies_memory = true;
+					let end_of_input = match eof_policy {
+						EofPolicy::Unchanged => "{}".to_string(),
+						EofPolicy::Zero => "memory[p] = 0".to_string(),
+						EofPolicy::MinusOne => format!("memory[p] = u{}::MAX", C::SIZE * 8),
+						EofPolicy::Error => "return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, \"Tried to read past the \
+						                     end of the input\"))"
+							.to_string(),
+					};
+					format!(
+						"{{\n{i}\toutput.flush()?;\n{i}\tlet mut byte = [0];\n{i}\tmatch input.read(&mut byte)? {{\n{i}\t\t0 => \
+						 {},\n{i}\t\t_ => memory[p] = byte[0].into(),\n{i}\t}}\n{i}}}",
+						end_of_input,
+						i = indent
+					)
+				},
+				Operation::Print { offset, .. } => format!("output.write_all(&[{} as u8])?;", cell_ref(*offset)),
+				Operation::MulAdd { offset, factor, .. } => {
+					self.modifies_memory = true;
+					// The factor as the unsigned cell, so the product wraps like the addition.
+					let factor = C::from_signed(*factor);
+					let target = cell_ref(*offset);
+					format!("{} = {}.wrapping_add(memory[p].wrapping_mul({}));", target, target, Into::<u32>::into(factor))
+				},
+				Operation::ScanZero(stride, _) => {
+					self.moves_pointer = true;
+					match *stride < 0 {
+						true => format!("while memory[p] != 0 {{\n{i}\tp -= {};\n{i}}}", -(*stride as i64), i = indent),
+						false => format!("while memory[p] != 0 {{\n{i}\tp += {};\n{i}}}", stride, i = indent),
+					}
+				},
+				// '#' is not parsed for transpiled programs.
+				Operation::DebugDump(_) => continue,
+			};
+			self.source.push_str(&format!("{}{}\n", indent, statement));
+		}
+	}
+}
+
+/// The Rust expression for the cell at offset from the pointer.
+fn cell_ref(offset: i32) -> String {
+	match offset {
+		0 => "memory[p]".to_string(),
+		_ if offset < 0 => format!("memory[p - {}]", -(offset as i64)),
+		_ => format!("memory[p + {}]", offset),
+	}
+}
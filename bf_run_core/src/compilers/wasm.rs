@@ -0,0 +1,287 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::CompilerOptions;
+use crate::{
+	cell::BfCell,
+	executors::{operations::*, EofPolicy, OverflowPolicy},
+};
+
+const DEFAULT_MEMORY_SIZE: usize = 65535;
+const WASM_PAGE_SIZE: usize = 65536;
+
+/// Function indices, the imported functions come before the functions of the module.
+const READ_BYTE_FUNCTION: u32 = 0;
+const WRITE_BYTE_FUNCTION: u32 = 1;
+const RUN_FUNCTION: u32 = 2;
+
+/// Local of the run function, holding the address of the current cell.
+const POINTER_LOCAL: u32 = 0;
+/// Local of the run function, holding the result of read_byte.
+const INPUT_LOCAL: u32 = 1;
+
+/// Compiles brainfuck code to a binary WebAssembly module, with cells of type C.
+///
+/// The module imports "env.read_byte", which returns the next byte of the input or -1 at the end of the input,
+/// and "env.write_byte", which is called with each byte of the output.
+/// It exports the function "run", which runs the program, and the memory holding the cells.
+/// The pointer starts at the center of the memory, and accesses outside of the memory trap,
+/// as does reading past the end of the input with EofPolicy::Error.
+pub struct WasmCompiler<C: BfCell> {
+	operations: Operations<C>,
+	options:    CompilerOptions,
+}
+impl<C: BfCell> WasmCompiler<C> {
+	pub fn new(code: &str, options: CompilerOptions) -> Result<WasmCompiler<C>, ParseError> {
+		let mut operations = Operations::conv_string_to_operations(code, false)?;
+		if options.enable_optimizations {
+			operations.optimize(OverflowPolicy::Wrapping);
+		}
+		Ok(WasmCompiler { operations, options })
+	}
+
+	/// Creates the contents of the module file.
+	pub fn compile(&self) -> Vec<u8> {
+		let memory_size = self.options.memory_size.unwrap_or(DEFAULT_MEMORY_SIZE);
+		let memory_pages = (memory_size * C::SIZE).div_ceil(WASM_PAGE_SIZE).max(1);
+
+		let mut module = Vec::new();
+		module.extend_from_slice(b"\0asm");
+		module.extend_from_slice(&1u32.to_le_bytes()); // Version.
+
+		// Types: read_byte, write_byte and run.
+		let types: [&[u8]; 3] = [&[0x60, 0x00, 0x01, 0x7f], &[0x60, 0x01, 0x7f, 0x00], &[0x60, 0x00, 0x00]];
+		add_section(&mut module, 1, types.len() as u32, &types.concat());
+
+		let mut imports = Vec::new();
+		for (name, type_index) in [("read_byte", READ_BYTE_FUNCTION), ("write_byte", WRITE_BYTE_FUNCTION)] {
+			add_name(&mut imports, "env");
+			add_name(&mut imports, name);
+			imports.push(0x00); // Function.
+			add_unsigned(&mut imports, type_index);
+		}
+		add_section(&mut module, 2, 2, &imports);
+
+		add_section(&mut module, 3, 1, &[RUN_FUNCTION as u8]); // The type of run.
+
+		let mut memories = vec![0x00]; // Limits without a maximum.
+		add_unsigned(&mut memories, memory_pages as u32);
+		add_section(&mut module, 5, 1, &memories);
+
+		let mut exports = Vec::new();
+		add_name(&mut exports, "run");
+		exports.push(0x00); // Function.
+		add_unsigned(&mut exports, RUN_FUNCTION);
+		add_name(&mut exports, "memory");
+		exports.push(0x02); // Memory.
+		add_unsigned(&mut exports, 0);
+		add_section(&mut module, 7, 2, &exports);
+
+		let mut body = vec![0x01, 0x02, 0x7f]; // Two i32 locals, the pointer and the input.
+		add_i32_const(&mut body, ((memory_size / 2) * C::SIZE) as i32);
+		body.push(0x21); // local.set
+		add_unsigned(&mut body, POINTER_LOCAL);
+		WasmCompiler::convert_operations(&self.operations, self.options.eof_policy, &mut body);
+		body.push(0x0b); // end
+		let mut code = Vec::new();
+		add_unsigned(&mut code, body.len() as u32);
+		code.extend_from_slice(&body);
+		add_section(&mut module, 10, 1, &code);
+
+		module
+	}
+
+	fn convert_operations(operations: &[Operation<C>], eof_policy: EofPolicy, code: &mut Vec<u8>) {
+		operations.iter().for_each(|operation| match operation {
+			Operation::Mod { offset, value, .. } => {
+				// The address of the store, and of the load.
+				add_address::<C>(code, *offset);
+				add_address::<C>(code, *offset);
+				add_load::<C>(code, *offset);
+				add_i32_const(code, (*value).into());
+				code.push(0x6a); // i32.add
+				add_store::<C>(code, *offset);
+			},
+			Operation::Move(move_value, _) => add_move::<C>(code, *move_value),
+			Operation::Loop(operations, _) => {
+				add_loop_start::<C>(code);
+				WasmCompiler::convert_operations(operations, eof_policy, code);
+				add_loop_end(code);
+			},
+			Operation::Set { offset, value, .. } => {
+				add_address::<C>(code, *offset);
+				add_i32_const(code, Into::<u32>::into(*value) as i32);
+				add_store::<C>(code, *offset);
+			},
+			Operation::GetInput(_) => {
+				code.push(0x10); // call
+				add_unsigned(code, READ_BYTE_FUNCTION);
+				code.push(0x22); // local.tee
+				add_unsigned(code, INPUT_LOCAL);
+				add_i32_const(code, 0);
+				code.extend_from_slice(&[0x48, 0x04, 0x40]); // i32.lt_s, if
+				match eof_policy {
+					EofPolicy::Unchanged => (),
+					EofPolicy::Zero | EofPolicy::MinusOne => {
+						add_address::<C>(code, 0);
+						add_i32_const(code, if eof_policy == EofPolicy::Zero { 0 } else { -1 });
+						add_store::<C>(code, 0);
+					},
+					EofPolicy::Error => code.push(0x00), // unreachable
+				}
+				code.push(0x05); // else
+				add_address::<C>(code, 0);
+				code.push(0x20); // local.get
+				add_unsigned(code, INPUT_LOCAL);
+				add_store::<C>(code, 0);
+				code.push(0x0b); // end
+			},
+			Operation::Print { offset, .. } => {
+				add_address::<C>(code, *offset);
+				add_load::<C>(code, *offset);
+				add_i32_const(code, 0xff);
+				code.push(0x71); // i32.and
+				code.push(0x10); // call
+				add_unsigned(code, WRITE_BYTE_FUNCTION);
+			},
+			Operation::MulAdd { offset, factor, .. } => {
+				// The address of the store, and of the load.
+				add_address::<C>(code, *offset);
+				add_address::<C>(code, *offset);
+				add_load::<C>(code, *offset);
+				add_address::<C>(code, 0);
+				add_load::<C>(code, 0);
+				add_i32_const(code, (*factor).into());
+				code.push(0x6c); // i32.mul
+				code.push(0x6a); // i32.add
+				add_store::<C>(code, *offset);
+			},
+			Operation::ScanZero(stride, _) => {
+				add_loop_start::<C>(code);
+				add_move::<C>(code, *stride);
+				add_loop_end(code);
+			},
+			// '#' is not parsed for compiled programs.
+			Operation::DebugDump(_) => (),
+		});
+	}
+}
+
+/// Adds a section with the given id, containing count entries.
+fn add_section(module: &mut Vec<u8>, id: u8, count: u32, entries: &[u8]) {
+	let mut contents = Vec::new();
+	add_unsigned(&mut contents, count);
+	contents.extend_from_slice(entries);
+	module.push(id);
+	add_unsigned(module, contents.len() as u32);
+	module.extend_from_slice(&contents);
+}
+
+fn add_name(code: &mut Vec<u8>, name: &str) {
+	add_unsigned(code, name.len() as u32);
+	code.extend_from_slice(name.as_bytes());
+}
+
+/// Adds value in the unsigned LEB128 encoding.
+fn add_unsigned(code: &mut Vec<u8>, mut value: u32) {
+	loop {
+		let byte = (value & 0x7f) as u8;
+		value >>= 7;
+		if value == 0 {
+			code.push(byte);
+			return;
+		}
+		code.push(byte | 0x80);
+	}
+}
+
+/// Adds "i32.const value", with value in the signed LEB128 encoding.
+fn add_i32_const(code: &mut Vec<u8>, mut value: i32) {
+	code.push(0x41);
+	loop {
+		let byte = (value & 0x7f) as u8;
+		value >>= 7;
+		if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+			code.push(byte);
+			return;
+		}
+		code.push(byte | 0x80);
+	}
+}
+
+/// Byte offset of the cell at offset from the pointer.
+fn byte_offset<C: BfCell>(offset: i32) -> i32 {
+	offset.wrapping_mul(C::SIZE as i32)
+}
+
+/// Pushes the address that add_load and add_store with the same offset access.
+/// Positive offsets are encoded in the memory argument of the access instead, as it can not be negative.
+fn add_address<C: BfCell>(code: &mut Vec<u8>, offset: i32) {
+	code.push(0x20); // local.get
+	add_unsigned(code, POINTER_LOCAL);
+	if offset < 0 {
+		add_i32_const(code, byte_offset::<C>(offset));
+		code.push(0x6a); // i32.add
+	}
+}
+
+/// Adds the memory argument of an access to the cell at offset from the address from add_address.
+fn add_memory_argument<C: BfCell>(code: &mut Vec<u8>, offset: i32) {
+	add_unsigned(code, C::SIZE.trailing_zeros()); // Alignment.
+	add_unsigned(code, byte_offset::<C>(offset.max(0)) as u32);
+}
+
+fn add_load<C: BfCell>(code: &mut Vec<u8>, offset: i32) {
+	code.push(match C::SIZE {
+		1 => 0x2d, // i32.load8_u
+		2 => 0x2f, // i32.load16_u
+		_ => 0x28, // i32.load
+	});
+	add_memory_argument::<C>(code, offset);
+}
+
+/// Stores the lowest bits of the value on the stack, that fit in the cell.
+fn add_store<C: BfCell>(code: &mut Vec<u8>, offset: i32) {
+	code.push(match C::SIZE {
+		1 => 0x3a, // i32.store8
+		2 => 0x3b, // i32.store16
+		_ => 0x36, // i32.store
+	});
+	add_memory_argument::<C>(code, offset);
+}
+
+fn add_move<C: BfCell>(code: &mut Vec<u8>, move_value: i32) {
+	code.push(0x20); // local.get
+	add_unsigned(code, POINTER_LOCAL);
+	add_i32_const(code, byte_offset::<C>(move_value));
+	code.push(0x6a); // i32.add
+	code.push(0x21); // local.set
+	add_unsigned(code, POINTER_LOCAL);
+}
+
+/// Starts a block with a loop inside, which is left through the block when the current value is zero.
+fn add_loop_start<C: BfCell>(code: &mut Vec<u8>) {
+	code.extend_from_slice(&[0x02, 0x40, 0x03, 0x40]); // block, loop
+	add_address::<C>(code, 0);
+	add_load::<C>(code, 0);
+	code.extend_from_slice(&[0x45, 0x0d, 0x01]); // i32.eqz, br_if to the end of the block.
+}
+
+/// Ends the loop and the block from add_loop_start, branching back to the start of the loop.
+fn add_loop_end(code: &mut Vec<u8>) {
+	code.extend_from_slice(&[0x0c, 0x00, 0x0b, 0x0b]); // br to the loop, end, end
+}
@@ -15,91 +15,165 @@
 	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use super::Executor;
-use crate::bf_memory::BfMemory;
+use std::io::{Read, Write};
+
+use super::{
+	operations::{Operations, ParseError, SourcePosition},
+	print_debug_dump, BfIo, Executor, ExecutorOptions, Limits, RunResult, RunStatus, RuntimeError,
+};
+use crate::{
+	bf_memory::BfMemory,
+	cell::{BfCell, WrappingInteger},
+};
 
 #[derive(Debug)]
-pub struct BfInterpreter<T> {
-	memory:  T,
-	code:    String,
-	verbose: bool,
+pub struct BfInterpreter<T, R, W> {
+	memory:     T,
+	code:       String,
+	io:         BfIo<R, W>,
+	options:    ExecutorOptions,
+	mem_index:  i32,
+	steps:      u64,
+	limits:     Limits,
+	/// Byte offset in code, that the search for the next instruction starts at.
+	position:   usize,
+	/// Byte offsets of the first instruction in each loop that is being executed.
+	loop_stack: Vec<usize>,
 }
-impl<T: BfMemory + std::fmt::Debug> Executor<T> for BfInterpreter<T> {
-	fn new(code: String, bf_memory: T, _enable_optimizations: bool, verbose: bool) -> BfInterpreter<T> {
-		BfInterpreter { memory: bf_memory, code, verbose }
+impl<T: BfMemory + std::fmt::Debug, R: Read, W: Write> Executor<T, R, W> for BfInterpreter<T, R, W> {
+	fn new(code: String, bf_memory: T, io: BfIo<R, W>, options: ExecutorOptions) -> Result<BfInterpreter<T, R, W>, ParseError> {
+		// Validate the loop structure up front, so start never encounters unbalanced brackets.
+		Operations::<T::Cell>::conv_string_to_operations(code.as_ref(), options.debug_dump)?;
+		let limits = Limits::new(&options);
+		Ok(BfInterpreter {
+			memory: bf_memory,
+			code,
+			io,
+			options,
+			mem_index: 0,
+			steps: 0,
+			limits,
+			position: 0,
+			loop_stack: Vec::new(),
+		})
 	}
 
-	fn start(mut self) {
-		let mut mem_index = 0i32;
-		let mut iterator = self.code.chars();
-		let mut loop_stack = Vec::new();
-
-		while let Some(character) = iterator.next() {
-			match character {
-				'+' => {
-					let mem_ref = self.memory.get_ref(mem_index);
-					*mem_ref = mem_ref.wrapping_add(1);
-				},
-				'-' => {
-					let mem_ref = self.memory.get_ref(mem_index);
-					*mem_ref = mem_ref.wrapping_sub(1);
-				},
-				'<' => mem_index -= 1,
-				'>' => mem_index += 1,
-				',' => BfInterpreter::<T>::get_char(self.memory.get_ref(mem_index)),
-				'.' => BfInterpreter::<T>::print_char(*self.memory.get_ref(mem_index)),
-				'[' => {
-					if *self.memory.get_ref(mem_index) != 0 {
-						loop_stack.push(iterator.clone());
-					}
-					else {
-						BfInterpreter::<T>::skip_loops(&mut iterator);
-					}
-				},
-				']' => {
-					if *self.memory.get_ref(mem_index) != 0 {
-						iterator = loop_stack.last().unwrap_or_else(|| panic!("] found, while no loops had been started!")).clone();
-					}
-					else {
-						loop_stack.pop();
-					}
-				},
-				_ => (),
-			}
-		}
-		if self.verbose {
+	fn start(mut self) -> RunResult<T> {
+		self.limits = Limits::new(&self.options);
+		let result = self.run().and(self.io.flush_output());
+		if self.options.verbose {
 			println!("\nINFO: Memory after running:\n{:?}", self.memory);
 		}
+		let status = result.map_or_else(RunStatus::Error, |_| RunStatus::Finished);
+		RunResult { status, memory: self.memory, pointer: self.mem_index, steps: Some(self.steps) }
 	}
 }
-impl<T: BfMemory + std::fmt::Debug> BfInterpreter<T> {
-	fn skip_loops(iterator: &mut std::str::Chars<'_>) {
-		while let Some(character) = iterator.next() {
+impl<T: BfMemory + std::fmt::Debug, R: Read, W: Write> BfInterpreter<T, R, W> {
+	fn run(&mut self) -> Result<(), RuntimeError> {
+		while self.step()? {}
+		Ok(())
+	}
+
+	/// Executes the next instruction, returns false without executing anything if the program has finished.
+	pub fn step(&mut self) -> Result<bool, RuntimeError> {
+		let Some(position) = self.next_instruction()
+		else {
+			self.position = self.code.len();
+			return Ok(false);
+		};
+		self.limits.check(self.steps)?;
+		self.position = position + 1;
+		match self.code.as_bytes()[position] {
+			instruction @ (b'+' | b'-') => {
+				let value = if instruction == b'+' { 1 } else { -1 };
+				let mem_ref = self.memory.try_get_ref(self.mem_index)?;
+				*mem_ref = mem_ref
+					.add_with_overflow(value, self.options.overflow)
+					.ok_or_else(|| RuntimeError::CellOverflow(SourcePosition::from_byte_offset(&self.code, position)))?;
+			},
+			b'<' => self.mem_index = self.memory.move_pointer(self.mem_index, -1)?,
+			b'>' => self.mem_index = self.memory.move_pointer(self.mem_index, 1)?,
+			b',' => {
+				let mem_ref = self.memory.try_get_ref(self.mem_index)?;
+				*mem_ref = self.io.get_char(*mem_ref)?;
+			},
+			b'.' => self.io.print_char(self.memory.try_get_ref(self.mem_index)?.low_byte())?,
+			b'#' => {
+				self.io.flush_output()?;
+				print_debug_dump(&self.memory, self.mem_index);
+			},
+			b'[' => {
+				if *self.memory.try_get_ref(self.mem_index)? != T::Cell::ZERO {
+					self.loop_stack.push(self.position);
+				}
+				else {
+					self.position = self.loop_end(self.position);
+				}
+			},
+			_ => {
+				if *self.memory.try_get_ref(self.mem_index)? != T::Cell::ZERO {
+					self.position = *self.loop_stack.last().expect("brackets are validated in new");
+				}
+				else {
+					self.loop_stack.pop();
+				}
+			},
+		}
+		self.steps += 1;
+		Ok(true)
+	}
+
+	/// Byte offset of the instruction that step executes next, None if the program has finished.
+	/// '#' is only an instruction, when the debug dump is enabled in the options.
+	pub fn next_instruction(&self) -> Option<usize> {
+		let debug_dump = self.options.debug_dump;
+		self.code.as_bytes()[self.position..]
+			.iter()
+			.position(|character| {
+				matches!(character, b'+' | b'-' | b'<' | b'>' | b',' | b'.' | b'[' | b']') || (debug_dump && *character == b'#')
+			})
+			.map(|distance| self.position + distance)
+	}
+
+	/// Returns the byte offset after the ']' that ends the loop, which starts at loop_start.
+	fn loop_end(&self, loop_start: usize) -> usize {
+		let mut depth = 0;
+		for (position, character) in self.code.bytes().enumerate().skip(loop_start) {
 			match character {
-				'[' => BfInterpreter::<T>::skip_loops(iterator),
-				']' => return,
+				b'[' => depth += 1,
+				b']' if depth == 0 => return position + 1,
+				b']' => depth -= 1,
 				_ => (),
 			}
 		}
+		unreachable!("brackets are validated in new")
+	}
+
+	/// Number of loops that are being executed.
+	pub fn loop_depth(&self) -> usize {
+		self.loop_stack.len()
 	}
 
-	#[inline(never)]
-	fn get_char(target: &mut u8) {
-		use std::io::{stdin, Read};
-		let stdin = stdin();
-		let mut lock = stdin.lock();
-		let mut buf = [0u8; 1];
-		lock.read_exact(&mut buf).unwrap();
-		*target = buf[0];
+	pub fn code(&self) -> &str {
+		&self.code
 	}
 
-	#[inline(never)]
-	fn print_char(source: u8) {
-		print!("{}", source as char);
-		use std::io;
+	pub fn memory(&self) -> &T {
+		&self.memory
+	}
+
+	/// Index of the memory pointer.
+	pub fn pointer(&self) -> i32 {
+		self.mem_index
+	}
+
+	/// Number of executed instructions.
+	pub fn steps(&self) -> u64 {
+		self.steps
+	}
 
-		use io::Write;
-		let mut stdout = io::stdout();
-		stdout.flush().unwrap();
+	/// Writes the buffered output of the program.
+	pub fn flush_output(&mut self) -> Result<(), RuntimeError> {
+		self.io.flush_output()
 	}
 }
@@ -15,64 +15,213 @@
 	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use super::{operations::*, Executor};
-use crate::bf_memory::BfMemory;
+use std::{
+	collections::BTreeMap,
+	io::{Read, Write},
+};
+
+use super::{
+	ir::IrFormat, operations::*, print_debug_dump, BfIo, Executor, ExecutorOptions, Limits, OverflowPolicy, RunResult, RunStatus,
+	RuntimeError,
+};
+use crate::{
+	bf_memory::BfMemory,
+	cell::{BfCell, WrappingInteger},
+};
 
 #[derive(Debug)]
-pub struct BfOptInterpreter<T> {
+pub struct BfOptInterpreter<T: BfMemory, R, W> {
 	memory:     T,
-	operations: Operations,
-	verbose:    bool,
+	operations: Operations<T::Cell>,
+	io:         BfIo<R, W>,
+	options:    ExecutorOptions,
+	/// Whether the operations were optimized.
+	optimized:  bool,
 }
-impl<T: BfMemory + std::fmt::Debug> Executor<T> for BfOptInterpreter<T> {
-	fn new(code: String, bf_memory: T, enable_optimizations: bool, verbose: bool) -> BfOptInterpreter<T> {
-		let operations = Operations::conv_string_to_operations(code.as_ref());
+impl<T: BfMemory + std::fmt::Debug, R: Read, W: Write> Executor<T, R, W> for BfOptInterpreter<T, R, W> {
+	fn new(code: String, bf_memory: T, io: BfIo<R, W>, options: ExecutorOptions) -> Result<BfOptInterpreter<T, R, W>, ParseError> {
+		let operations = Operations::conv_string_to_operations(code.as_ref(), options.debug_dump)?;
 
-		let mut interpreter = BfOptInterpreter { memory: bf_memory, operations, verbose };
+		let optimized = options.enable_optimizations && bf_memory.allows_optimizations();
+		let mut interpreter = BfOptInterpreter { memory: bf_memory, operations, io, options, optimized };
 
-		if enable_optimizations {
-			interpreter.operations.optimize()
+		if interpreter.optimized {
+			interpreter.operations.optimize(interpreter.options.overflow)
 		};
-		if interpreter.verbose {
-			println!("Converted operations:\n{:?}", interpreter.get_ops());
+		if interpreter.options.verbose {
+			println!(
+				"Converted operations:\n{}",
+				interpreter.operations.to_ir(&IrFormat { spans: true, loop_stats: true })
+			);
 		}
-		interpreter
+		Ok(interpreter)
+	}
+
+	fn start(self) -> RunResult<T> {
+		self.run(None).0
+	}
+}
+impl<T: BfMemory + std::fmt::Debug, R: Read, W: Write> BfOptInterpreter<T, R, W> {
+	/// Runs the program like start, while counting the executions of each loop in the operations.
+	/// The loops that were replaced by the optimizer are not included, the profiles are ordered by their position in the code.
+	pub fn start_profiled(self) -> (RunResult<T>, Vec<LoopProfile>) {
+		let mut loops = BTreeMap::new();
+		BfOptInterpreter::<T, R, W>::collect_loops(&self.operations, self.optimized, &mut loops);
+		let (result, loops) = self.run(Some(loops));
+		(result, loops.map_or_else(Vec::new, |loops| loops.into_values().collect()))
 	}
 
-	fn start(mut self) {
-		let start_value = *self.memory.get_ref(0);
-		let (mem_index, cur_pos_value) = BfOptInterpreter::<T>::exec_operations_vec(0, start_value, &mut self.memory, &self.operations);
-		*self.memory.get_ref(mem_index) = cur_pos_value;
-		if self.verbose {
+	/// Adds an empty profile for each loop in operations, by the start of their source.
+	/// Loops are only marked as only_modifies when the operations were optimized, as the optimizer replaces most of them.
+	fn collect_loops(operations: &[Operation<T::Cell>], optimized: bool, loops: &mut BTreeMap<usize, LoopProfile>) {
+		for operation in operations {
+			if let Operation::Loop(loop_operations, span) = operation {
+				let only_modifies = optimized &&
+					loop_operations.iter().all(|operation| {
+						matches!(
+							operation,
+							Operation::Mod { .. } | Operation::Move(..) | Operation::Set { .. } | Operation::MulAdd { .. }
+						)
+					});
+				loops.insert(span.start, LoopProfile { span: *span, entries: 0, iterations: 0, steps: 0, only_modifies });
+				BfOptInterpreter::<T, R, W>::collect_loops(loop_operations, optimized, loops);
+			}
+		}
+	}
+
+	fn run(mut self, profile: Option<BTreeMap<usize, LoopProfile>>) -> (RunResult<T>, Option<BTreeMap<usize, LoopProfile>>) {
+		let limits = Limits::new(&self.options);
+		let mut state = ExecState {
+			mem_index: 0,
+			cur_pos_value: *self.memory.get_ref(0),
+			steps: 0,
+			limits,
+			overflow: self.options.overflow,
+			profile,
+		};
+		let result = BfOptInterpreter::<T, R, W>::exec_operations_vec(&mut state, &mut self.memory, &mut self.io, &self.operations);
+		*self.memory.get_ref(state.mem_index) = state.cur_pos_value;
+		let result = result.and(self.io.flush_output());
+		if self.options.verbose {
 			println!("\nINFO: Memory after running:\n{:?}", self.memory);
 		}
+		let status = result.map_or_else(RunStatus::Error, |_| RunStatus::Finished);
+		let run_result = RunResult { status, memory: self.memory, pointer: state.mem_index, steps: Some(state.steps) };
+		(run_result, state.profile)
 	}
-}
-impl<T: BfMemory + std::fmt::Debug> BfOptInterpreter<T> {
-	fn exec_operations_vec(mut mem_index: i32, mut cur_pos_value: u8, memory: &mut T, vec: &[Operation]) -> (i32, u8) {
-		vec.iter().for_each(|operation| match operation {
-			Operation::Mod(value) => cur_pos_value = cur_pos_value.wrapping_add(*value as u8),
-			Operation::Move(value) => {
-				*memory.get_ref(mem_index) = cur_pos_value;
-				mem_index -= value;
-				cur_pos_value = *memory.get_ref(mem_index);
-			},
-			Operation::Loop(operations) => {
-				while cur_pos_value != 0 {
-					let (new_mem_index, new_cur_pos_value) =
-						BfOptInterpreter::<T>::exec_operations_vec(mem_index, cur_pos_value, memory, operations);
-					mem_index = new_mem_index;
-					cur_pos_value = new_cur_pos_value;
-				}
-			},
-			Operation::SetValue(value) => cur_pos_value = *value,
-			Operation::GetInput => cur_pos_value = super::get_char(),
-			Operation::PrintOutput => super::print_char(cur_pos_value),
-		});
-		(mem_index, cur_pos_value)
+
+	fn exec_operations_vec(
+		state: &mut ExecState<T::Cell>, memory: &mut T, io: &mut BfIo<R, W>, vec: &[Operation<T::Cell>],
+	) -> Result<(), RuntimeError> {
+		for operation in vec {
+			state.limits.check(state.steps)?;
+			match operation {
+				Operation::Mod { offset: 0, value, span } => {
+					state.cur_pos_value = state.add(state.cur_pos_value, Into::<i32>::into(*value) as i64, span)?
+				},
+				Operation::Mod { offset, value, span } => {
+					let target = memory.try_get_ref(state.mem_index + offset)?;
+					*target = state.add(*target, Into::<i32>::into(*value) as i64, span)?;
+				},
+				Operation::Move(value, _) => {
+					let mem_index = memory.move_pointer(state.mem_index, *value)?;
+					// Written back first, as the edge policy may keep the pointer at the current cell.
+					*memory.get_ref(state.mem_index) = state.cur_pos_value;
+					state.cur_pos_value = *memory.try_get_ref(mem_index)?;
+					state.mem_index = mem_index;
+				},
+				Operation::Loop(operations, span) => {
+					let start_steps = state.steps;
+					let mut iterations = 0;
+					let result = BfOptInterpreter::<T, R, W>::exec_loop(state, memory, io, operations, &mut iterations);
+					if let Some(profile) = &mut state.profile {
+						let loop_profile = profile.get_mut(&span.start).expect("loops are collected before running");
+						loop_profile.entries += 1;
+						loop_profile.iterations += iterations;
+						loop_profile.steps += state.steps - start_steps;
+					}
+					result?;
+				},
+				Operation::Set { offset: 0, value, .. } => state.cur_pos_value = *value,
+				Operation::Set { offset, value, .. } => *memory.try_get_ref(state.mem_index + offset)? = *value,
+				Operation::GetInput(_) => state.cur_pos_value = io.get_char(state.cur_pos_value)?,
+				Operation::Print { offset: 0, .. } => io.print_char(state.cur_pos_value.low_byte())?,
+				Operation::Print { offset, .. } => io.print_char(memory.try_get_ref(state.mem_index + offset)?.low_byte())?,
+				Operation::MulAdd { offset, factor, span } => {
+					let current: u32 = state.cur_pos_value.into();
+					let product = current as i64 * Into::<i32>::into(*factor) as i64;
+					let target = memory.try_get_ref(state.mem_index + offset)?;
+					*target = state.add(*target, product, span)?;
+				},
+				Operation::DebugDump(_) => {
+					*memory.get_ref(state.mem_index) = state.cur_pos_value;
+					io.flush_output()?;
+					print_debug_dump(memory, state.mem_index);
+				},
+				Operation::ScanZero(stride, _) => {
+					if state.cur_pos_value != T::Cell::ZERO {
+						*memory.get_ref(state.mem_index) = state.cur_pos_value;
+						state.mem_index = memory.scan_zero(state.mem_index + stride, *stride)?;
+						state.cur_pos_value = T::Cell::ZERO;
+					}
+				},
+			}
+			state.steps += 1;
+		}
+		Ok(())
 	}
 
-	pub fn get_ops(&self) -> &[Operation] {
+	/// Executes the operations of a loop until the current value is zero, counting the iterations.
+	fn exec_loop(
+		state: &mut ExecState<T::Cell>, memory: &mut T, io: &mut BfIo<R, W>, operations: &[Operation<T::Cell>], iterations: &mut u64,
+	) -> Result<(), RuntimeError> {
+		while state.cur_pos_value != T::Cell::ZERO {
+			BfOptInterpreter::<T, R, W>::exec_operations_vec(state, memory, io, operations)?;
+			// Every iteration counts as a step, so empty loops also use fuel.
+			state.steps += 1;
+			*iterations += 1;
+			state.limits.check(state.steps)?;
+		}
+		Ok(())
+	}
+
+	pub fn get_ops(&self) -> &[Operation<T::Cell>] {
 		self.operations.as_slice()
 	}
 }
+
+/// Position and cached current value of the memory pointer, while executing operations.
+struct ExecState<C> {
+	mem_index:     i32,
+	cur_pos_value: C,
+	steps:         u64,
+	limits:        Limits,
+	overflow:      OverflowPolicy,
+	/// Counters of each loop by the start of its source, only collected by start_profiled.
+	profile:       Option<BTreeMap<usize, LoopProfile>>,
+}
+impl<C: BfCell> ExecState<C> {
+	/// Adds value to cell with the overflow policy, overflows are reported at the start of the span of the operation.
+	fn add(&self, cell: C, value: i64, span: &SourceSpan) -> Result<C, RuntimeError> {
+		cell.add_with_overflow(value, self.overflow)
+			.ok_or_else(|| RuntimeError::CellOverflow(span.start_position()))
+	}
+}
+
+/// Counters of a loop in the operations, collected by BfOptInterpreter::start_profiled.
+#[derive(Debug, Clone)]
+pub struct LoopProfile {
+	/// Source code of the loop.
+	pub span:          SourceSpan,
+	/// Number of times the loop was reached.
+	pub entries:       u64,
+	/// Number of times the operations in the loop were executed.
+	pub iterations:    u64,
+	/// Number of steps taken in the loop, including the steps of nested loops.
+	pub steps:         u64,
+	/// Whether the loop only modifies values and moves the memory pointer, after the operations were optimized.
+	/// Such loops are usually replaced by the optimizer, unless the loop does not return to its starting position,
+	/// or does not change the value at its starting position by 1.
+	/// Always false when the operations were not optimized.
+	pub only_modifies: bool,
+}
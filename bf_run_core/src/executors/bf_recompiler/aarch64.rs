@@ -0,0 +1,418 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::marker::PhantomData;
+
+use super::{Backend, JitEnvironment, RecompiledOps, JIT_ERROR};
+use crate::{cell::BfCell, executors::OverflowPolicy};
+
+/// Emits AArch64 machine code, using the AAPCS64 calling convention for calls to the trampoline functions.
+/// Moving the memory pointer always calls move_ref, so any memory type is supported.
+/// The cells are loaded and stored with ldrb/ldrh/ldr and strb/strh/str, depending on the size of C.
+///
+/// "w19" register stores value of the currently pointed to value.
+/// "w20" register stores the current index.
+/// "x21" register points to the last used position in memory.
+/// "x22" register stores the address of the JitContext.
+/// These registers are callee saved, so they are kept across calls to the trampoline functions.
+pub(crate) struct AArch64Backend<C> {
+	cell: PhantomData<C>,
+}
+impl<C: BfCell> Backend<C> for AArch64Backend<C> {
+	fn add_entry(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps) {
+		push_instructions(recompiled_memory, &[
+			0xa9bd7bfd, // stp x29, x30, [sp, #-48]!
+			0x910003fd, // mov x29, sp
+			0xa90153f3, // stp x19, x20, [sp, #16]
+			0xa9025bf5, // stp x21, x22, [sp, #32]
+		]);
+		add_mov_imm64(22, usize::from_ne_bytes(environment.context_addr) as u64, recompiled_memory);
+
+		// Get initial value of "w19".
+		push_instructions(recompiled_memory, &[
+			0xaa1603e0, // mov x0, x22
+			0x52800001, // mov w1, #0
+		]);
+		add_fn_call(environment.get_ref_fn_addr, recompiled_memory);
+		push_instructions(recompiled_memory, &[
+			0xaa0003f5,             // mov x21, x0
+			sized::<C>(0x394002b3), // ldrb w19, [x21]
+			0x52800014,             // mov w20, #0
+		]);
+	}
+
+	/// Machine code that puts "w19" back into memory, stores the index from "w20"
+	/// in the JitContext, restores the callee saved registers and returns from the recompiled code.
+	fn exit_ops(environment: &JitEnvironment) -> RecompiledOps {
+		let mut exit_ops = RecompiledOps::default();
+		push_instructions(&mut exit_ops, &[sized::<C>(0x390002b3)]); // strb w19, [x21]
+		add_mov_imm64(9, environment.pointer_addr as u64, &mut exit_ops);
+		push_instructions(&mut exit_ops, &[
+			0xb9000134, // str w20, [x9]
+			0xa9425bf5, // ldp x21, x22, [sp, #32]
+			0xa94153f3, // ldp x19, x20, [sp, #16]
+			0xa8c37bfd, // ldp x29, x30, [sp], #48
+			0xd65f03c0, // ret
+		]);
+		exit_ops
+	}
+
+	fn add_mod(environment: &JitEnvironment, offset: i32, value: C::Signed, source_offset: usize, recompiled_memory: &mut RecompiledOps) {
+		if environment.overflow != OverflowPolicy::Wrapping {
+			// The result is calculated in 64 bits, in "x9".
+			if offset == 0 {
+				add_mov_imm64(10, Into::<i32>::into(value) as i64 as u64, recompiled_memory);
+				push_instructions(recompiled_memory, &[0x8b0a0269]); // add x9, x19, x10
+				AArch64Backend::<C>::add_overflow_check(environment, source_offset, recompiled_memory);
+				push_instructions(recompiled_memory, &[0x2a0903f3]); // mov w19, w9
+			}
+			else {
+				AArch64Backend::<C>::add_offset_ref(environment, offset, recompiled_memory);
+				push_instructions(recompiled_memory, &[sized::<C>(0x39400009)]); // ldrb w9, [x0]
+				add_mov_imm64(10, Into::<i32>::into(value) as i64 as u64, recompiled_memory);
+				push_instructions(recompiled_memory, &[0x8b0a0129]); // add x9, x9, x10
+				AArch64Backend::<C>::add_overflow_check(environment, source_offset, recompiled_memory);
+				push_instructions(recompiled_memory, &[sized::<C>(0x39000009)]); // strb w9, [x0]
+			}
+			return;
+		}
+
+		let value: u32 = C::from_signed(value).into();
+		if offset == 0 {
+			add_to_register(19, value, recompiled_memory);
+			// Keep "w19" within the size of the cells.
+			match C::SIZE {
+				1 => push_instructions(recompiled_memory, &[0x12001e73]), // and w19, w19, #0xff
+				2 => push_instructions(recompiled_memory, &[0x12003e73]), // and w19, w19, #0xffff
+				_ => (),
+			}
+		}
+		else {
+			AArch64Backend::<C>::add_offset_ref(environment, offset, recompiled_memory);
+			push_instructions(recompiled_memory, &[sized::<C>(0x39400009)]); // ldrb w9, [x0]
+			add_to_register(9, value, recompiled_memory);
+			push_instructions(recompiled_memory, &[sized::<C>(0x39000009)]); // strb w9, [x0]
+		}
+	}
+
+	fn add_move(environment: &JitEnvironment, move_value: i32, recompiled_memory: &mut RecompiledOps) {
+		// Put value of "w19" back into its position in bf_memory.
+		push_instructions(recompiled_memory, &[sized::<C>(0x390002b3)]); // strb w19, [x21]
+
+		push_instructions(recompiled_memory, &[
+			0xd10043ff, // sub sp, sp, #16
+			0xf90003f4, // str x20, [sp]. The current index, replaced by move_ref.
+			0xaa1603e0, // mov x0, x22
+		]);
+		add_mov_imm32(1, move_value as u32, recompiled_memory);
+		push_instructions(recompiled_memory, &[0x910003e2]); // mov x2, sp
+		add_fn_call(environment.move_ref_fn_addr, recompiled_memory);
+		push_instructions(recompiled_memory, &[
+			0xb94003f4, // ldr w20, [sp]. The moved index.
+			0x910043ff, // add sp, sp, #16
+		]);
+		// "x21" still points to the previous value, if move_ref failed.
+		AArch64Backend::<C>::add_null_check(environment, recompiled_memory);
+		push_instructions(recompiled_memory, &[
+			0xaa0003f5,             // mov x21, x0
+			sized::<C>(0x394002b3), // ldrb w19, [x21]
+		]);
+	}
+
+	fn add_loop(_environment: &JitEnvironment, loop_block: RecompiledOps, recompiled_memory: &mut RecompiledOps) -> usize {
+		let block_instructions = (loop_block.len() / 4) as i32;
+
+		push_instructions(recompiled_memory, &[
+			0x35000053,                     // cbnz w19, #8. Over the forward branch.
+			branch(block_instructions + 2), // b, past the backwards branch.
+		]);
+		let block_offset = recompiled_memory.len();
+		recompiled_memory.push_opcodes(&loop_block);
+		push_instructions(recompiled_memory, &[branch(-block_instructions - 2)]); // b, to the cbnz.
+		block_offset
+	}
+
+	fn add_set(environment: &JitEnvironment, offset: i32, value: C, recompiled_memory: &mut RecompiledOps) {
+		if offset == 0 {
+			add_mov_imm32(19, value.into(), recompiled_memory);
+		}
+		else {
+			AArch64Backend::<C>::add_offset_ref(environment, offset, recompiled_memory);
+			add_mov_imm32(9, value.into(), recompiled_memory);
+			push_instructions(recompiled_memory, &[sized::<C>(0x39000009)]); // strb w9, [x0]
+		}
+	}
+
+	/// fetch_input writes the input directly into the current cell, so "w19" is stored before the call and reloaded after it.
+	fn add_get_input(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps) {
+		push_instructions(recompiled_memory, &[
+			sized::<C>(0x390002b3), // strb w19, [x21]
+			0xaa1603e0,             // mov x0, x22
+			0xaa1503e1,             // mov x1, x21
+		]);
+		add_fn_call(environment.fetch_input_fn_addr, recompiled_memory);
+		AArch64Backend::<C>::add_error_check(environment, recompiled_memory);
+		push_instructions(recompiled_memory, &[sized::<C>(0x394002b3)]); // ldrb w19, [x21]
+	}
+
+	/// Always calls print_u8, which writes to the output buffer when output is buffered.
+	/// Only the lowest byte of the cell is printed.
+	fn add_print(environment: &JitEnvironment, offset: i32, recompiled_memory: &mut RecompiledOps) {
+		if offset == 0 {
+			push_instructions(recompiled_memory, &[0x2a1303e1]); // mov w1, w19
+		}
+		else {
+			AArch64Backend::<C>::add_offset_ref(environment, offset, recompiled_memory);
+			push_instructions(recompiled_memory, &[0x39400001]); // ldrb w1, [x0]
+		}
+		push_instructions(recompiled_memory, &[0xaa1603e0]); // mov x0, x22
+		add_fn_call(environment.print_u8_fn_addr, recompiled_memory);
+		AArch64Backend::<C>::add_error_check(environment, recompiled_memory);
+	}
+
+	fn add_mul_add(
+		environment: &JitEnvironment, offset: i32, factor: C::Signed, source_offset: usize, recompiled_memory: &mut RecompiledOps,
+	) {
+		AArch64Backend::<C>::add_offset_ref(environment, offset, recompiled_memory);
+		push_instructions(recompiled_memory, &[sized::<C>(0x39400009)]); // ldrb w9, [x0]
+		if environment.overflow == OverflowPolicy::Wrapping {
+			add_mov_imm32(10, C::from_signed(factor).into(), recompiled_memory);
+			push_instructions(recompiled_memory, &[0x1b0a2669]); // madd w9, w19, w10, w9
+		}
+		else {
+			// The result is calculated in 64 bits, where it can not overflow.
+			add_mov_imm64(10, Into::<i32>::into(factor) as i64 as u64, recompiled_memory);
+			push_instructions(recompiled_memory, &[0x9b0a2669]); // madd x9, x19, x10, x9
+			AArch64Backend::<C>::add_overflow_check(environment, source_offset, recompiled_memory);
+		}
+		push_instructions(recompiled_memory, &[sized::<C>(0x39000009)]); // strb w9, [x0]
+	}
+
+	fn add_scan_zero(environment: &JitEnvironment, stride: i32, recompiled_memory: &mut RecompiledOps) {
+		// A loop that only moves the memory pointer.
+		let mut loop_block = RecompiledOps::default();
+		AArch64Backend::<C>::add_move(environment, stride, &mut loop_block);
+		AArch64Backend::<C>::add_loop(environment, loop_block, recompiled_memory);
+	}
+
+	fn add_debug_dump(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps) {
+		push_instructions(recompiled_memory, &[
+			sized::<C>(0x390002b3), // strb w19, [x21]
+			0xaa1603e0,             // mov x0, x22
+			0x2a1403e1,             // mov w1, w20
+		]);
+		add_fn_call(environment.debug_dump_fn_addr, recompiled_memory);
+		AArch64Backend::<C>::add_error_check(environment, recompiled_memory);
+	}
+
+	fn add_fuel_check(environment: &JitEnvironment, fuel_counter_addr: usize, recompiled_memory: &mut RecompiledOps) {
+		add_mov_imm64(9, fuel_counter_addr as u64, recompiled_memory);
+		push_instructions(recompiled_memory, &[
+			0xf940012a, // ldr x10, [x9]
+			0xf100054a, // subs x10, x10, #1
+			0xf900012a, // str x10, [x9]
+		]);
+
+		let mut refuel_block = RecompiledOps::default();
+		push_instructions(&mut refuel_block, &[0xaa1603e0]); // mov x0, x22
+		add_fn_call(environment.refuel_fn_addr, &mut refuel_block);
+		AArch64Backend::<C>::add_error_check(environment, &mut refuel_block);
+
+		// b.ne, over the refuel block.
+		push_instructions(recompiled_memory, &[branch_cond(0x1, (refuel_block.len() / 4) as i32 + 1)]);
+		recompiled_memory.push_opcodes(&refuel_block);
+	}
+
+	/// The memory is only accessed through get_ref and get_offset_ref, in rust code.
+	fn fault_recovery_ops(_environment: &JitEnvironment) -> Option<RecompiledOps> {
+		None
+	}
+}
+impl<C: BfCell> AArch64Backend<C> {
+	/// Puts a reference to the value at offset from the current index into "x0".
+	/// The reference in "x21" is renewed, as get_offset_ref may reallocate the memory.
+	fn add_offset_ref(environment: &JitEnvironment, offset: i32, recompiled_memory: &mut RecompiledOps) {
+		push_instructions(recompiled_memory, &[
+			sized::<C>(0x390002b3), // strb w19, [x21]
+			0xd10043ff,             // sub sp, sp, #16
+			0xf90003f4,             // str x20, [sp]. The current index, for get_offset_ref.
+			0xaa1603e0,             // mov x0, x22
+		]);
+		add_mov_imm32(1, offset as u32, recompiled_memory);
+		push_instructions(recompiled_memory, &[0x910003e2]); // mov x2, sp
+		add_fn_call(environment.get_offset_ref_fn_addr, recompiled_memory);
+		push_instructions(recompiled_memory, &[
+			0xf94003f5,             // ldr x21, [sp]. Reference to the current value, from get_offset_ref.
+			0x910043ff,             // add sp, sp, #16
+			sized::<C>(0x394002b3), // ldrb w19, [x21]
+		]);
+		AArch64Backend::<C>::add_null_check(environment, recompiled_memory);
+	}
+
+	/// Exits from the recompiled code if "x0" holds a null reference,
+	/// which memories with checked access return after storing an error.
+	fn add_null_check(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps) {
+		if !environment.checked_access {
+			return;
+		}
+		let exit_ops = AArch64Backend::<C>::exit_ops(environment);
+		// cbnz x0, over the exit_ops.
+		push_instructions(recompiled_memory, &[0xb5000000 | ((exit_ops.len() / 4) as u32 + 1) << 5]);
+		recompiled_memory.push_opcodes(&exit_ops);
+	}
+
+	/// Checks whether the 64 bit result in "x9" fits in a cell.
+	/// Saturates "x9" to the range of a cell, or calls cell_overflow and exits, depending on the overflow policy.
+	fn add_overflow_check(environment: &JitEnvironment, source_offset: usize, recompiled_memory: &mut RecompiledOps) {
+		add_mov_imm32(10, C::MAX.into(), recompiled_memory);
+		push_instructions(recompiled_memory, &[0xeb0a013f]); // cmp x9, x10. Negative results are higher as well.
+
+		let mut overflow_block = RecompiledOps::default();
+		match environment.overflow {
+			OverflowPolicy::Saturating => push_instructions(&mut overflow_block, &[
+				0x937ffd29, // asr x9, x9, #63. -1 for negative results, otherwise 0.
+				0xaa2903e9, // mvn x9, x9. 0 for negative results, otherwise all bits set.
+				0x8a0a0129, // and x9, x9, x10
+			]),
+			_ => {
+				push_instructions(&mut overflow_block, &[0xaa1603e0]); // mov x0, x22
+				add_mov_imm32(1, source_offset as u32, &mut overflow_block);
+				add_fn_call(environment.cell_overflow_fn_addr, &mut overflow_block);
+				AArch64Backend::<C>::add_error_check(environment, &mut overflow_block);
+			},
+		}
+		// b.ls, over the overflow block.
+		push_instructions(recompiled_memory, &[branch_cond(0x9, (overflow_block.len() / 4) as i32 + 1)]);
+		recompiled_memory.push_opcodes(&overflow_block);
+	}
+
+	/// Should be placed right after a call to a trampoline function.
+	/// Runs exit_ops, if the trampoline returned JIT_ERROR.
+	fn add_error_check(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps) {
+		let exit_ops = AArch64Backend::<C>::exit_ops(environment);
+		push_instructions(recompiled_memory, &[
+			0x72000000 | logical_imm_bit(JIT_ERROR),           // tst w0, #JIT_ERROR
+			branch_cond(0x0, (exit_ops.len() / 4) as i32 + 1), // b.eq, over the exit_ops.
+		]);
+		recompiled_memory.push_opcodes(&exit_ops);
+	}
+}
+
+fn push_instructions(recompiled_memory: &mut RecompiledOps, instructions: &[u32]) {
+	instructions
+		.iter()
+		.for_each(|instruction| recompiled_memory.push_opcodes(&instruction.to_le_bytes()));
+}
+
+/// Changes the size of a byte sized ldrb or strb instruction, to the size of the cells.
+fn sized<C: BfCell>(byte_instruction: u32) -> u32 {
+	byte_instruction | C::SIZE.trailing_zeros() << 30
+}
+
+/// Adds value to the 32 bit register with number register.
+/// Values that do not fit in a 12 bit immediate are moved into "w10" first.
+fn add_to_register(register: u32, value: u32, recompiled_memory: &mut RecompiledOps) {
+	if value <= 0xfff {
+		push_instructions(recompiled_memory, &[add_imm(register, register, value)]);
+	}
+	else {
+		add_mov_imm32(10, value, recompiled_memory);
+		push_instructions(recompiled_memory, &[0x0b0a0000 | register << 5 | register]); // add register, register, w10
+	}
+}
+
+/// Calls the function at function_addr through "x9".
+fn add_fn_call(function_addr: usize, recompiled_memory: &mut RecompiledOps) {
+	add_mov_imm64(9, function_addr as u64, recompiled_memory);
+	push_instructions(recompiled_memory, &[0xd63f0120]); // blr x9
+}
+
+/// Sets the 64 bit register with number register to value, using a movz and three movk.
+fn add_mov_imm64(register: u32, value: u64, recompiled_memory: &mut RecompiledOps) {
+	push_instructions(recompiled_memory, &[0xd2800000 | ((value & 0xffff) as u32) << 5 | register]); // movz
+	(1..4).for_each(|shift| {
+		let part = ((value >> (16 * shift)) & 0xffff) as u32;
+		push_instructions(recompiled_memory, &[0xf2800000 | shift << 21 | part << 5 | register]); // movk
+	});
+}
+
+/// Sets the 32 bit register with number register to value, using a movz and a movk.
+fn add_mov_imm32(register: u32, value: u32, recompiled_memory: &mut RecompiledOps) {
+	push_instructions(recompiled_memory, &[
+		mov_imm16(register, value as u16),                     // movz
+		0x72a00000 | ((value >> 16) & 0xffff) << 5 | register, // movk, lsl #16
+	]);
+}
+
+/// movz of a 16 bit value into the 32 bit register with number register.
+fn mov_imm16(register: u32, value: u16) -> u32 {
+	0x52800000 | (value as u32) << 5 | register
+}
+
+/// add with a 12 bit immediate, on 32 bit registers.
+fn add_imm(destination: u32, source: u32, value: u32) -> u32 {
+	0x11000000 | (value & 0xfff) << 10 | source << 5 | destination
+}
+
+/// Unconditional branch, by a number of instructions.
+fn branch(instructions: i32) -> u32 {
+	0x14000000 | (instructions as u32 & 0x3ffffff)
+}
+
+/// Conditional branch, by a number of instructions.
+fn branch_cond(condition: u32, instructions: i32) -> u32 {
+	0x54000000 | (instructions as u32 & 0x7ffff) << 5 | condition
+}
+
+/// Encoding of the bitmask immediate for a 32 bit logical instruction on w0, where the mask has a single bit set.
+fn logical_imm_bit(bit_value: u32) -> u32 {
+	let bit = bit_value.trailing_zeros();
+	// A single set bit, rotated right by (32 - bit) % 32.
+	((32 - bit) % 32) << 16 | 0x1f
+}
+
+/// Makes the instructions written to code visible to instruction fetches, which are not coherent with data
+/// writes on AArch64. Does the same as __clear_cache: cleans the data cache lines of code to the point of
+/// unification, invalidates the instruction cache lines, and synchronizes the instruction stream.
+#[cfg(target_arch = "aarch64")]
+pub(super) fn flush_instruction_cache(code: &[u8]) {
+	use std::arch::asm;
+
+	let start = code.as_ptr() as usize;
+	let end = start + code.len();
+	let cache_type: usize;
+	// CTR_EL0 is readable from user space on Linux, and the cache maintenance instructions only
+	// operate on the cache lines of code, which is mapped in this process.
+	unsafe {
+		asm!("mrs {}, ctr_el0", out(reg) cache_type, options(nomem, nostack));
+		// The line sizes are encoded as log2 of the number of 4 byte words.
+		let data_line = 4 << ((cache_type >> 16) & 0xf);
+		let instruction_line = 4 << (cache_type & 0xf);
+		let mut address = start & !(data_line - 1);
+		while address < end {
+			asm!("dc cvau, {}", in(reg) address, options(nostack));
+			address += data_line;
+		}
+		asm!("dsb ish", options(nostack));
+		let mut address = start & !(instruction_line - 1);
+		while address < end {
+			asm!("ic ivau, {}", in(reg) address, options(nostack));
+			address += instruction_line;
+		}
+		asm!("dsb ish", "isb", options(nostack));
+	}
+}
@@ -0,0 +1,461 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::SourceMap;
+use crate::{
+	cell::BfCell,
+	executors::{ir, operations::Operation},
+};
+
+/// Width of the column with the bytes of an instruction, which fits the 10 bytes of movabs.
+const BYTES_COLUMN_WIDTH: usize = 29;
+
+/// Creates a listing of the recompiled code, with the offset, bytes and instruction on each line.
+/// The instructions of each operation are preceded by a comment with the operation and its span.
+pub(super) fn listing<C: BfCell>(
+	code: &[u8], operations: &[Operation<C>], source_map: &SourceMap, fault_recovery_offset: Option<usize>,
+) -> String {
+	let mut descriptions = Vec::new();
+	describe_operations(operations, &mut descriptions);
+	let mut comments = vec![(0, "entry code".to_string())];
+	comments.extend(
+		source_map
+			.entries()
+			.iter()
+			.zip(descriptions)
+			.map(|((offset, span), description)| (*offset, format!("{} at {:?}", description, span))),
+	);
+	comments.push((source_map.end, "exit code".to_string()));
+	if let Some(fault_recovery_offset) = fault_recovery_offset {
+		comments.push((fault_recovery_offset, "fault recovery code".to_string()));
+	}
+
+	let instructions = if cfg!(target_arch = "x86_64") { disassemble(code) } else { instruction_words(code) };
+	let offset_width = format!("{:x}", code.len()).len().max(4);
+	let mut comments = comments.into_iter().peekable();
+	let mut listing = String::new();
+	for instruction in instructions {
+		while let Some((_, comment)) = comments.next_if(|(offset, _)| *offset <= instruction.offset) {
+			listing.push_str(&format!("; {}\n", comment));
+		}
+		let bytes = code[instruction.offset..instruction.offset + instruction.length]
+			.iter()
+			.map(|byte| format!("{:02x}", byte))
+			.collect::<Vec<_>>()
+			.join(" ");
+		listing.push_str(&format!(
+			"0x{:0offset_width$x}  {:<bytes_width$} {}\n",
+			instruction.offset,
+			bytes,
+			instruction.text,
+			offset_width = offset_width,
+			bytes_width = BYTES_COLUMN_WIDTH
+		));
+	}
+	listing
+}
+
+/// Adds a description of each operation to descriptions, in the order of the entries in the source map.
+fn describe_operations<C: BfCell>(operations: &[Operation<C>], descriptions: &mut Vec<String>) {
+	for operation in operations {
+		descriptions.push(ir::statement(operation));
+		if let Operation::Loop(operations, _) = operation {
+			describe_operations(operations, descriptions);
+			descriptions.push("end of loop".to_string());
+		}
+	}
+}
+
+/// Lists code as 32 bit words, for the fixed size instructions of AArch64.
+fn instruction_words(code: &[u8]) -> Vec<Instruction> {
+	code.chunks(4)
+		.enumerate()
+		.map(|(index, word)| {
+			let mut bytes = [0; 4];
+			bytes[..word.len()].copy_from_slice(word);
+			Instruction { offset: index * 4, length: word.len(), text: format!(".inst {:#010x}", u32::from_le_bytes(bytes)) }
+		})
+		.collect()
+}
+
+/// A decoded x86-64 instruction of the recompiled code.
+pub(super) struct Instruction {
+	pub offset: usize,
+	pub length: usize,
+	/// Intel syntax, with the targets of jumps as offsets in the code.
+	pub text:   String,
+}
+
+/// Decodes the x86-64 instructions that the recompiler and the memories emit.
+/// Bytes that do not start a known instruction are listed one at a time as "(bad)".
+pub(super) fn disassemble(code: &[u8]) -> Vec<Instruction> {
+	let mut instructions = Vec::new();
+	let mut offset = 0;
+	while offset < code.len() {
+		let mut decoder = Decoder { code, position: offset, rex: 0, operand_size_prefix: false, repeat_prefix: false };
+		let (length, text) = match decoder.decode() {
+			Some(text) => (decoder.position - offset, text),
+			None => (1, format!("(bad) {:#04x}", code[offset])),
+		};
+		instructions.push(Instruction { offset, length, text });
+		offset += length;
+	}
+	instructions
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Size {
+	Byte,
+	Word,
+	Dword,
+	Qword,
+	Xmm,
+	/// Memory operands of lea, which are not accessed.
+	Unsized,
+}
+
+const REGISTERS_64: [&str; 16] = [
+	"rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+];
+const REGISTERS_32: [&str; 16] = [
+	"eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d",
+];
+const REGISTERS_16: [&str; 16] = [
+	"ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w",
+];
+const REGISTERS_8: [&str; 16] = [
+	"al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b",
+];
+/// The byte registers 4 to 7, without a REX prefix.
+const HIGH_BYTE_REGISTERS: [&str; 4] = ["ah", "ch", "dh", "bh"];
+const CONDITIONS: [&str; 16] = ["o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g"];
+/// Instructions of the opcodes 0x80, 0x81 and 0x83, by the reg field of the ModRM byte.
+const ARITHMETIC: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+/// Instructions of the opcode 0xc1, by the reg field of the ModRM byte.
+const SHIFTS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "sal", "sar"];
+
+struct Decoder<'a> {
+	code:                &'a [u8],
+	position:            usize,
+	rex:                 u8,
+	operand_size_prefix: bool,
+	repeat_prefix:       bool,
+}
+impl Decoder<'_> {
+	fn decode(&mut self) -> Option<String> {
+		let mut opcode = self.byte()?;
+		loop {
+			match opcode {
+				0x66 => self.operand_size_prefix = true,
+				0xf3 => self.repeat_prefix = true,
+				// The REX prefix must be the last prefix.
+				0x40..=0x4f if self.rex == 0 => {
+					self.rex = opcode;
+					opcode = self.byte()?;
+					break;
+				},
+				_ => break,
+			}
+			opcode = self.byte()?;
+		}
+		let size = self.operand_size();
+		let text = match opcode {
+			0x00 | 0x01 | 0x29 | 0x31 | 0x39 | 0x85 | 0x88 | 0x89 => {
+				let mnemonic = match opcode {
+					0x00 | 0x01 => "add",
+					0x29 => "sub",
+					0x31 => "xor",
+					0x39 => "cmp",
+					0x85 => "test",
+					_ => "mov",
+				};
+				let size = if opcode == 0x00 || opcode == 0x88 { Size::Byte } else { size };
+				let (reg, rm) = self.modrm(size)?;
+				format!("{} {}, {}", mnemonic, rm, self.register(reg, size))
+			},
+			0x3b | 0x8a | 0x8b => {
+				let mnemonic = if opcode == 0x3b { "cmp" } else { "mov" };
+				let size = if opcode == 0x8a { Size::Byte } else { size };
+				let (reg, rm) = self.modrm(size)?;
+				format!("{} {}, {}", mnemonic, self.register(reg, size), rm)
+			},
+			0x8d => {
+				let (reg, rm) = self.modrm(Size::Unsized)?;
+				format!("lea {}, {}", self.register(reg, size), rm)
+			},
+			0x50..=0x57 => format!("push {}", REGISTERS_64[self.opcode_register(opcode)]),
+			0x58..=0x5f => format!("pop {}", REGISTERS_64[self.opcode_register(opcode)]),
+			0x69 => {
+				let (reg, rm) = self.modrm(size)?;
+				let immediate = self.immediate_32()?;
+				format!("imul {}, {}, {}", self.register(reg, size), rm, hex(immediate as i64))
+			},
+			0x70..=0x7f => {
+				let displacement = self.immediate_8()?;
+				format!("j{} {}", CONDITIONS[opcode as usize & 0xf], self.jump_target(displacement as i64))
+			},
+			0xeb => {
+				let displacement = self.immediate_8()?;
+				format!("jmp {}", self.jump_target(displacement as i64))
+			},
+			0xe9 => {
+				let displacement = self.immediate_32()?;
+				format!("jmp {}", self.jump_target(displacement as i64))
+			},
+			0x80 | 0x81 | 0x83 => {
+				let size = if opcode == 0x80 { Size::Byte } else { size };
+				let (reg, rm) = self.modrm(size)?;
+				let immediate = match (opcode, size) {
+					(0x81, Size::Word) => self.immediate_16()? as i64,
+					(0x81, _) => self.immediate_32()? as i64,
+					_ => self.immediate_8()? as i64,
+				};
+				format!("{} {}, {}", ARITHMETIC[reg as usize & 7], rm, hex(immediate))
+			},
+			0xb0..=0xb7 => {
+				let register = self.register(self.opcode_register(opcode) as u8, Size::Byte);
+				format!("mov {}, {}", register, hex(self.immediate_8()? as u8 as i64))
+			},
+			0xb8..=0xbf => {
+				let register = self.register(self.opcode_register(opcode) as u8, size);
+				match size {
+					Size::Qword => format!("movabs {}, {}", register, hex(self.immediate_64()?)),
+					Size::Word => format!("mov {}, {}", register, hex(self.immediate_16()? as u16 as i64)),
+					_ => format!("mov {}, {}", register, hex(self.immediate_32()? as u32 as i64)),
+				}
+			},
+			0xc1 => {
+				let (reg, rm) = self.modrm(size)?;
+				format!("{} {}, {}", SHIFTS[reg as usize & 7], rm, hex(self.immediate_8()? as i64))
+			},
+			0xc3 => "ret".to_string(),
+			0xc6 | 0xc7 => {
+				let size = if opcode == 0xc6 { Size::Byte } else { size };
+				let (_, rm) = self.modrm(size)?;
+				let immediate = match size {
+					Size::Byte => self.immediate_8()? as u8 as i64,
+					Size::Word => self.immediate_16()? as u16 as i64,
+					_ => self.immediate_32()? as i64,
+				};
+				format!("mov {}, {}", rm, hex(immediate))
+			},
+			0xf7 => {
+				let (reg, rm) = self.modrm(size)?;
+				match reg & 7 {
+					0 => {
+						let immediate = if size == Size::Word { self.immediate_16()? as i64 } else { self.immediate_32()? as i64 };
+						format!("test {}, {}", rm, hex(immediate))
+					},
+					2 => format!("not {}", rm),
+					3 => format!("neg {}", rm),
+					_ => return None,
+				}
+			},
+			0xff => {
+				// The operand of call is always 64 bit.
+				let size = if (self.peek()? >> 3) & 7 == 2 { Size::Qword } else { size };
+				let (reg, rm) = self.modrm(size)?;
+				match reg & 7 {
+					0 => format!("inc {}", rm),
+					1 => format!("dec {}", rm),
+					2 => format!("call {}", rm),
+					_ => return None,
+				}
+			},
+			0x0f => self.decode_two_byte()?,
+			_ => return None,
+		};
+		Some(text)
+	}
+
+	/// Decodes the instructions with the 0x0f escape byte.
+	fn decode_two_byte(&mut self) -> Option<String> {
+		let opcode = self.byte()?;
+		let text = match opcode {
+			0x80..=0x8f => {
+				let displacement = self.immediate_32()?;
+				format!("j{} {}", CONDITIONS[opcode as usize & 0xf], self.jump_target(displacement as i64))
+			},
+			0xb6 | 0xb7 => {
+				let (reg, rm) = self.modrm(if opcode == 0xb6 { Size::Byte } else { Size::Word })?;
+				format!("movzx {}, {}", self.register(reg, self.operand_size()), rm)
+			},
+			0xbc | 0xbd => {
+				let size = self.operand_size();
+				let (reg, rm) = self.modrm(size)?;
+				format!("{} {}, {}", if opcode == 0xbc { "bsf" } else { "bsr" }, self.register(reg, size), rm)
+			},
+			0x6f if self.repeat_prefix => {
+				let (reg, rm) = self.modrm(Size::Xmm)?;
+				format!("movdqu {}, {}", self.register(reg, Size::Xmm), rm)
+			},
+			0x74..=0x76 | 0xef if self.operand_size_prefix => {
+				let mnemonic = match opcode {
+					0x74 => "pcmpeqb",
+					0x75 => "pcmpeqw",
+					0x76 => "pcmpeqd",
+					_ => "pxor",
+				};
+				let (reg, rm) = self.modrm(Size::Xmm)?;
+				format!("{} {}, {}", mnemonic, self.register(reg, Size::Xmm), rm)
+			},
+			0xd7 if self.operand_size_prefix => {
+				let (reg, rm) = self.modrm(Size::Xmm)?;
+				format!("pmovmskb {}, {}", self.register(reg, Size::Dword), rm)
+			},
+			_ => return None,
+		};
+		Some(text)
+	}
+
+	fn operand_size(&self) -> Size {
+		if self.rex & 0x08 != 0 {
+			Size::Qword
+		}
+		else if self.operand_size_prefix {
+			Size::Word
+		}
+		else {
+			Size::Dword
+		}
+	}
+
+	fn register(&self, number: u8, size: Size) -> String {
+		let number = number as usize;
+		match size {
+			Size::Byte if self.rex == 0 && (4..8).contains(&number) => HIGH_BYTE_REGISTERS[number - 4].to_string(),
+			Size::Byte => REGISTERS_8[number].to_string(),
+			Size::Word => REGISTERS_16[number].to_string(),
+			Size::Dword => REGISTERS_32[number].to_string(),
+			Size::Qword | Size::Unsized => REGISTERS_64[number].to_string(),
+			Size::Xmm => format!("xmm{}", number),
+		}
+	}
+
+	/// The register in the low bits of opcode, extended by REX.B.
+	fn opcode_register(&self, opcode: u8) -> usize {
+		(opcode & 7 | (self.rex & 1) << 3) as usize
+	}
+
+	/// Decodes a ModRM byte, with its SIB byte and displacement.
+	/// Returns the reg field extended by REX.R, and the register or memory operand of size.
+	fn modrm(&mut self, size: Size) -> Option<(u8, String)> {
+		let modrm = self.byte()?;
+		let mode = modrm >> 6;
+		let reg = (modrm >> 3) & 7 | (self.rex & 4) << 1;
+		let rm = modrm & 7;
+		if mode == 3 {
+			return Some((reg, self.register(rm | (self.rex & 1) << 3, size)));
+		}
+
+		let mut parts = Vec::new();
+		let mut displacement = None;
+		match rm {
+			4 => {
+				let sib = self.byte()?;
+				let index = (sib >> 3) & 7 | (self.rex & 2) << 2;
+				let base = sib & 7;
+				if mode == 0 && base == 5 {
+					displacement = Some(self.immediate_32()? as i64);
+				}
+				else {
+					parts.push(REGISTERS_64[(base | (self.rex & 1) << 3) as usize].to_string());
+				}
+				// An index of rsp means no index.
+				if index != 4 {
+					match 1 << (sib >> 6) {
+						1 => parts.push(REGISTERS_64[index as usize].to_string()),
+						scale => parts.push(format!("{}*{}", REGISTERS_64[index as usize], scale)),
+					}
+				}
+			},
+			5 if mode == 0 => {
+				parts.push("rip".to_string());
+				displacement = Some(self.immediate_32()? as i64);
+			},
+			_ => parts.push(REGISTERS_64[(rm | (self.rex & 1) << 3) as usize].to_string()),
+		}
+		// The displacement follows the SIB byte.
+		match mode {
+			1 => displacement = Some(self.immediate_8()? as i64),
+			2 => displacement = Some(self.immediate_32()? as i64),
+			_ => (),
+		}
+
+		let mut address = parts.join(" + ");
+		match displacement {
+			Some(displacement) if address.is_empty() => address = hex(displacement),
+			Some(displacement) if displacement < 0 => address.push_str(&format!(" - {}", hex(-displacement))),
+			Some(displacement) => address.push_str(&format!(" + {}", hex(displacement))),
+			None => (),
+		}
+		let keyword = match size {
+			Size::Byte => "byte ",
+			Size::Word => "word ",
+			Size::Dword => "dword ",
+			Size::Qword => "qword ",
+			Size::Xmm => "xmmword ",
+			Size::Unsized => "",
+		};
+		Some((reg, format!("{}[{}]", keyword, address)))
+	}
+
+	/// The offset that a jump by displacement from the end of the instruction goes to.
+	fn jump_target(&self, displacement: i64) -> String {
+		format!("{:#x}", self.position as i64 + displacement)
+	}
+
+	fn peek(&self) -> Option<u8> {
+		self.code.get(self.position).copied()
+	}
+
+	fn byte(&mut self) -> Option<u8> {
+		let byte = self.peek()?;
+		self.position += 1;
+		Some(byte)
+	}
+
+	fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+		let bytes = self.code.get(self.position..self.position + N)?.try_into().ok()?;
+		self.position += N;
+		Some(bytes)
+	}
+
+	fn immediate_8(&mut self) -> Option<i8> {
+		Some(self.byte()? as i8)
+	}
+
+	fn immediate_16(&mut self) -> Option<i16> {
+		Some(i16::from_le_bytes(self.bytes()?))
+	}
+
+	fn immediate_32(&mut self) -> Option<i32> {
+		Some(i32::from_le_bytes(self.bytes()?))
+	}
+
+	fn immediate_64(&mut self) -> Option<i64> {
+		Some(i64::from_le_bytes(self.bytes()?))
+	}
+}
+
+/// Formats value in hexadecimal, with a minus sign for negative values.
+fn hex(value: i64) -> String {
+	match value < 0 {
+		true => format!("-{:#x}", value.unsigned_abs()),
+		false => format!("{:#x}", value),
+	}
+}
@@ -0,0 +1,566 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::io::{Read, Write};
+
+use super::{
+	ir::IrFormat, operations::*, print_debug_dump, BfIo, Executor, ExecutorOptions, Limits, OverflowPolicy, RunResult, RunStatus,
+	RuntimeError,
+};
+use crate::{bf_memory, bf_memory::guarded, cell::BfCell};
+extern crate memmap;
+use memmap::{Mmap, MmapOptions};
+
+mod aarch64;
+mod disassembler;
+mod x86_64;
+
+use aarch64::AArch64Backend;
+use x86_64::X86_64Backend;
+
+/// Whether the recompiler has a backend for the processor architecture of this build.
+pub const RECOMPILER_SUPPORTED: bool = cfg!(any(target_arch = "x86_64", target_arch = "aarch64"));
+
+const PAGE_SIZE: usize = 4096;
+/// Bit set in the return value of a trampoline function when it failed.
+/// The error itself is stored in the JitContext.
+const JIT_ERROR: u32 = 0x100;
+
+#[derive(Debug, Default)]
+pub struct RecompiledOps {
+	recompiled_ops: Vec<u8>,
+}
+impl RecompiledOps {
+	pub fn push_opcodes(&mut self, opcodes: &[u8]) {
+		opcodes.iter().for_each(|opcode| {
+			self.recompiled_ops.push(*opcode);
+		});
+	}
+
+	pub fn add_fn_call(&mut self, function_addr: usize) {
+		// Pushing important registers to stack:
+		self.push_opcodes(&[0x52, 0x51]); // Push rdx, push rcx.
+		// Aligning Stack:
+		self.push_opcodes(&[0x55]); // Push rbp. Save rbp for later restoration
+		self.push_opcodes(&[0x48, 0x89, 0xe5]); // mov rsp into rbp. Backing up rsp for later restore.
+		self.push_opcodes(&[0x48, 0x83, 0xe4, 0xf0]); // and rsp, -16, Align stack to 16-byte
+		self.push_opcodes(&[0x48, 0x81, 0xec, 0x80, 0x00, 0x00, 0x00]); // Subtract 128, for "Red zone" per sysv64 convention
+		// Placing reference to function in rax:
+		self.push_opcodes(&[0x48, 0xb8]); // movabs rax
+		self.push_opcodes(&function_addr.to_ne_bytes()); // argument for movabs rax
+		// Call function
+		self.push_opcodes(&[0xff, 0xd0]); // call rax
+		// Restore stack.
+		self.push_opcodes(&[0x48, 0x89, 0xec]); // mov rbp into rsp. Restores rsp.
+		self.push_opcodes(&[0x5d]); // pop rbp. Restores rbp.
+		// Restoring registers from stack:
+		self.push_opcodes(&[0x59, 0x5a]); // pop rcx, pop rdx.
+	}
+
+	/// Pushes the x86-64 instruction that operates on values of type C.
+	/// byte_form is used for 8-bit cells, otherwise wide_form is used, with an operand size prefix for 16-bit cells.
+	pub fn push_sized<C: BfCell>(&mut self, byte_form: &[u8], wide_form: &[u8]) {
+		match C::SIZE {
+			1 => self.push_opcodes(byte_form),
+			2 => {
+				self.push(0x66); // Operand size prefix.
+				self.push_opcodes(wide_form);
+			},
+			_ => self.push_opcodes(wide_form),
+		}
+	}
+
+	/// Pushes value as an immediate of the width of C.
+	pub fn push_cell<C: BfCell>(&mut self, value: C) {
+		let value: u32 = value.into();
+		self.push_opcodes(&value.to_ne_bytes()[..C::SIZE]);
+	}
+
+	/// Puts the current value in "dl", "dx" or "edx" back into memory at "rax".
+	pub fn add_store_current<C: BfCell>(&mut self) {
+		self.push_sized::<C>(&[0x88, 0x10], &[0x89, 0x10]); // mov [rax], dl
+	}
+
+	/// Loads the current value into "dl", "dx" or "edx" from memory at "rax".
+	pub fn add_load_current<C: BfCell>(&mut self) {
+		self.push_sized::<C>(&[0x8a, 0x10], &[0x8b, 0x10]); // mov dl, [rax]
+	}
+
+	/// Compares the current value in "dl", "dx" or "edx" to 0.
+	pub fn add_compare_current_zero<C: BfCell>(&mut self) {
+		self.push_sized::<C>(&[0x80, 0xfa, 0x00], &[0x83, 0xfa, 0x00]); // cmp dl, 0
+	}
+
+	/// Should be placed right after a call to a trampoline function, that had "rax" pushed before the call.
+	/// Restores "rax" and runs exit_ops, if the trampoline returned JIT_ERROR.
+	pub fn add_error_check(&mut self, exit_ops: &RecompiledOps) {
+		self.push_opcodes(&[0x89, 0xc6]); // mov esi, eax. Keep return value, as rax is restored.
+		self.push(0x58); // Pop rax
+		self.push_opcodes(&[0xf7, 0xc6]); // test esi
+		self.push_opcodes(&JIT_ERROR.to_ne_bytes()); // argument for test esi.
+		self.push_opcodes(&[0x74, exit_ops.len() as u8]); // Jump equal, over the exit_ops.
+		self.push_opcodes(exit_ops);
+	}
+}
+impl std::ops::Deref for RecompiledOps {
+	type Target = Vec<u8>;
+
+	fn deref(&self) -> &Vec<u8> {
+		&self.recompiled_ops
+	}
+}
+impl std::ops::DerefMut for RecompiledOps {
+	fn deref_mut(&mut self) -> &mut Vec<u8> {
+		&mut self.recompiled_ops
+	}
+}
+
+/// State that the recompiled code accesses through the trampoline functions.
+/// The recompiled code receives the address of this struct as a context pointer.
+#[derive(Debug)]
+struct JitContext<T, R, W> {
+	memory:       T,
+	io:           BfIo<R, W>,
+	error:        Option<RuntimeError>,
+	/// Index of the memory pointer, stored by the recompiled code when it exits.
+	pointer:      i32,
+	limits:       Option<Limits>,
+	/// Loop iterations left before the recompiled code calls refuel.
+	fuel_counter: u64,
+	/// The value fuel_counter was last set to.
+	last_fuel:    u64,
+	/// Loop iterations counted, up to the last call of refuel.
+	loop_steps:   u64,
+	/// The source code, for the positions in overflow errors.
+	code:         String,
+}
+impl<T, R, W> JitContext<T, R, W> {
+	fn store_error(&mut self, error: RuntimeError) -> u32 {
+		self.error = Some(error);
+		JIT_ERROR
+	}
+
+	/// Sets fuel_counter to the number of loop iterations allowed before the limits must be checked again.
+	fn set_fuel_counter(&mut self) {
+		if let Some(limits) = &self.limits {
+			// At least one iteration must pass, since the counter is checked after decrementing it.
+			self.last_fuel = limits.steps_until_check(self.loop_steps).max(1);
+			self.fuel_counter = self.last_fuel;
+		}
+	}
+}
+
+/// Addresses used by the recompiled code, to call into or exit to rust code.
+struct JitEnvironment {
+	context_addr:           [u8; 8],
+	output_buffer_addr:     Option<usize>,
+	pointer_addr:           usize,
+	/// Only set when the execution has fuel or timeout limits.
+	fuel_counter_addr:      Option<usize>,
+	overflow:               OverflowPolicy,
+	/// Address of the memory in the JitContext.
+	memory_addr:            usize,
+	/// Whether the memory can return null references, see BfMemory::CHECKED_ACCESS.
+	checked_access:         bool,
+	get_ref_fn_addr:        usize,
+	move_ref_fn_addr:       usize,
+	get_offset_ref_fn_addr: usize,
+	print_u8_fn_addr:       usize,
+	flush_output_fn_addr:   usize,
+	fetch_input_fn_addr:    usize,
+	refuel_fn_addr:         usize,
+	cell_overflow_fn_addr:  usize,
+	debug_dump_fn_addr:     usize,
+}
+
+/// Creates the machine code of the recompiled operations on values of type C, for a processor architecture.
+/// Each function appends the machine code for one operation to recompiled_memory.
+trait Backend<C: BfCell> {
+	/// Machine code run when entering the recompiled code, loading the value at index 0.
+	fn add_entry(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps);
+	/// Machine code that puts the current value back into memory, stores the index
+	/// in the JitContext, and returns from the recompiled code.
+	fn exit_ops(environment: &JitEnvironment) -> RecompiledOps;
+	/// With other overflow policies than OverflowPolicy::Wrapping, the result is checked,
+	/// and calls cell_overflow with source_offset when trapping.
+	fn add_mod(environment: &JitEnvironment, offset: i32, value: C::Signed, source_offset: usize, recompiled_memory: &mut RecompiledOps);
+	fn add_move(environment: &JitEnvironment, move_value: i32, recompiled_memory: &mut RecompiledOps);
+	/// Adds loop_block, repeating it while the current value is not zero.
+	/// Returns the offset of loop_block in recompiled_memory.
+	fn add_loop(environment: &JitEnvironment, loop_block: RecompiledOps, recompiled_memory: &mut RecompiledOps) -> usize;
+	fn add_set(environment: &JitEnvironment, offset: i32, value: C, recompiled_memory: &mut RecompiledOps);
+	fn add_get_input(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps);
+	fn add_print(environment: &JitEnvironment, offset: i32, recompiled_memory: &mut RecompiledOps);
+	/// Checks the result like add_mod.
+	fn add_mul_add(
+		environment: &JitEnvironment, offset: i32, factor: C::Signed, source_offset: usize, recompiled_memory: &mut RecompiledOps,
+	);
+	fn add_scan_zero(environment: &JitEnvironment, stride: i32, recompiled_memory: &mut RecompiledOps);
+	/// Puts the current value back into memory, and calls debug_dump with the index.
+	fn add_debug_dump(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps);
+	/// Decrements the fuel counter, and calls refuel when it reaches zero.
+	fn add_fuel_check(environment: &JitEnvironment, fuel_counter_addr: usize, recompiled_memory: &mut RecompiledOps);
+	/// Machine code that the guarded memory continues at, when the recompiled code accessed a guard region.
+	/// It stores the index in the JitContext, and returns from the recompiled code.
+	/// None if the backend only accesses the memory through the trampoline functions.
+	fn fault_recovery_ops(environment: &JitEnvironment) -> Option<RecompiledOps>;
+
+	/// Appends the machine code of operations to recompiled_memory,
+	/// and adds the position of the machine code of each operation to source_map.
+	fn convert_to_machine_code(
+		operations: &[Operation<C>], environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps, source_map: &mut SourceMap,
+	) {
+		operations.iter().for_each(|operation| {
+			source_map.entries.push((recompiled_memory.len(), operation.span()));
+			match operation {
+				Operation::Mod { offset, value, span } => Self::add_mod(environment, *offset, *value, span.start, recompiled_memory),
+				Operation::Move(move_value, _) => Self::add_move(environment, *move_value, recompiled_memory),
+				Operation::Loop(operations, span) => {
+					let mut loop_block = RecompiledOps::default();
+					let mut loop_source_map = SourceMap::default();
+					Self::convert_to_machine_code(operations, environment, &mut loop_block, &mut loop_source_map);
+					let operations_len = loop_block.len();
+					if let Some(fuel_counter_addr) = environment.fuel_counter_addr {
+						Self::add_fuel_check(environment, fuel_counter_addr, &mut loop_block);
+					}
+					let block_offset = Self::add_loop(environment, loop_block, recompiled_memory);
+					source_map
+						.entries
+						.extend(loop_source_map.entries.into_iter().map(|(offset, span)| (block_offset + offset, span)));
+					// The fuel check and the jump back belong to the loop itself.
+					source_map.entries.push((block_offset + operations_len, *span));
+				},
+				Operation::Set { offset, value, .. } => Self::add_set(environment, *offset, *value, recompiled_memory),
+				Operation::GetInput(_) => Self::add_get_input(environment, recompiled_memory),
+				Operation::Print { offset, .. } => Self::add_print(environment, *offset, recompiled_memory),
+				Operation::MulAdd { offset, factor, span } => {
+					Self::add_mul_add(environment, *offset, *factor, span.start, recompiled_memory)
+				},
+				Operation::ScanZero(stride, _) => Self::add_scan_zero(environment, *stride, recompiled_memory),
+				Operation::DebugDump(_) => Self::add_debug_dump(environment, recompiled_memory),
+			}
+		});
+		source_map.end = recompiled_memory.len();
+	}
+
+	/// Creates the complete machine code, for running operations.
+	/// Also returns the position of the fault recovery code, if the backend has any,
+	/// and the source map of the machine code.
+	fn recompile(operations: &[Operation<C>], environment: &JitEnvironment) -> (RecompiledOps, Option<usize>, SourceMap) {
+		let mut recompiled_memory = RecompiledOps::default();
+		let mut source_map = SourceMap::default();
+		Self::add_entry(environment, &mut recompiled_memory);
+		Self::convert_to_machine_code(operations, environment, &mut recompiled_memory, &mut source_map);
+		recompiled_memory.push_opcodes(&Self::exit_ops(environment));
+		let fault_recovery_offset = Self::fault_recovery_ops(environment).map(|fault_recovery_ops| {
+			let fault_recovery_offset = recompiled_memory.len();
+			recompiled_memory.push_opcodes(&fault_recovery_ops);
+			fault_recovery_offset
+		});
+		(recompiled_memory, fault_recovery_offset, source_map)
+	}
+}
+
+/// Maps offsets in the recompiled machine code to the source code of the operations they were created from.
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap {
+	/// Offset of the machine code of each operation, ordered by offset.
+	/// Loops have an entry at their comparison, and at the code after their operations.
+	entries: Vec<(usize, SourceSpan)>,
+	/// Offset of the end of the machine code of the operations, where the exit code starts.
+	end:     usize,
+}
+impl SourceMap {
+	pub fn entries(&self) -> &[(usize, SourceSpan)] {
+		&self.entries
+	}
+
+	/// The span of the operation that the machine code at offset was created from,
+	/// None for the entry, exit and fault recovery code.
+	pub fn span_at(&self, offset: usize) -> Option<SourceSpan> {
+		if offset >= self.end {
+			return None;
+		}
+		// Operations without machine code share their offset with the next operation, so the last entry is used.
+		let index = self.entries.partition_point(|(entry_offset, _)| *entry_offset <= offset).checked_sub(1)?;
+		Some(self.entries[index].1)
+	}
+}
+
+pub struct BfRecompiler<T: bf_memory::BfMemory, R, W> {
+	context:               Box<JitContext<T, R, W>>,
+	recompiled_memory:     RecompiledOps,
+	/// Position of the code that faults in the guard regions of the guarded memory continue at.
+	fault_recovery_offset: Option<usize>,
+	source_map:            SourceMap,
+	/// The recompiled operations, for the listing of the machine code.
+	operations:            Operations<T::Cell>,
+	options:               ExecutorOptions,
+}
+impl<T: bf_memory::BfMemory + std::fmt::Debug, R: Read, W: Write> Executor<T, R, W> for BfRecompiler<T, R, W> {
+	fn new(code: String, bf_memory: T, io: BfIo<R, W>, options: ExecutorOptions) -> Result<BfRecompiler<T, R, W>, ParseError> {
+		// Get operations.
+		let mut operations = Operations::<T::Cell>::conv_string_to_operations(code.as_ref(), options.debug_dump)?;
+		if options.enable_optimizations && bf_memory.allows_optimizations() {
+			operations.optimize(options.overflow);
+		}
+		if options.verbose {
+			println!(
+				"Operations before recompilation to machine code:\n{}",
+				operations.to_ir(&IrFormat { spans: true, loop_stats: true })
+			);
+		}
+
+		let mut context = Box::new(JitContext {
+			memory: bf_memory,
+			io,
+			error: None,
+			pointer: 0,
+			limits: None,
+			fuel_counter: 0,
+			last_fuel: 0,
+			loop_steps: 0,
+			code,
+		}); // Heap allocate context.
+		let context_addr = (context.as_mut() as *mut JitContext<T, R, W> as usize).to_ne_bytes();
+		let has_limits = options.fuel.is_some() || options.timeout.is_some();
+		let environment = JitEnvironment {
+			context_addr,
+			output_buffer_addr: context.io.output_buffer_addr(),
+			pointer_addr: &mut context.pointer as *mut i32 as usize,
+			fuel_counter_addr: has_limits.then_some(&mut context.fuel_counter as *mut u64 as usize),
+			overflow: options.overflow,
+			memory_addr: &context.memory as *const T as usize,
+			checked_access: T::CHECKED_ACCESS,
+			get_ref_fn_addr: BfRecompiler::<T, R, W>::get_ref as *const () as usize,
+			move_ref_fn_addr: BfRecompiler::<T, R, W>::move_ref as *const () as usize,
+			get_offset_ref_fn_addr: BfRecompiler::<T, R, W>::get_offset_ref as *const () as usize,
+			print_u8_fn_addr: BfRecompiler::<T, R, W>::print_u8 as *const () as usize,
+			flush_output_fn_addr: BfRecompiler::<T, R, W>::flush_output as *const () as usize,
+			fetch_input_fn_addr: BfRecompiler::<T, R, W>::fetch_input as *const () as usize,
+			refuel_fn_addr: BfRecompiler::<T, R, W>::refuel as *const () as usize,
+			cell_overflow_fn_addr: BfRecompiler::<T, R, W>::cell_overflow as *const () as usize,
+			debug_dump_fn_addr: BfRecompiler::<T, R, W>::debug_dump as *const () as usize,
+		};
+
+		let (recompiled_memory, fault_recovery_offset, source_map) = if cfg!(target_arch = "x86_64") {
+			X86_64Backend::<T>::recompile(&operations, &environment)
+		}
+		else if cfg!(target_arch = "aarch64") {
+			AArch64Backend::<T::Cell>::recompile(&operations, &environment)
+		}
+		else {
+			panic!("Recompiler is not implemented for this processor architecture!");
+		};
+
+		let recompiler = BfRecompiler { context, recompiled_memory, fault_recovery_offset, source_map, operations, options };
+		if recompiler.options.verbose {
+			println!("Recompiled instructions:\n{}", recompiler.listing());
+		}
+		Ok(recompiler)
+	}
+
+	fn start(mut self) -> RunResult<T> {
+		if self.options.fuel.is_some() || self.options.timeout.is_some() {
+			self.context.limits = Some(Limits::new(&self.options));
+			self.context.set_fuel_counter();
+		}
+		let result = match self.create_exec_memory() {
+			Ok(execute_memory) => {
+				let code_addr = execute_memory.as_ptr() as usize;
+				if let (Some(fault_recovery_offset), Some(region)) = (self.fault_recovery_offset, self.context.memory.guard_region()) {
+					let code = code_addr..code_addr + execute_memory.len();
+					guarded::set_fault_recovery(code, code_addr + fault_recovery_offset, region);
+				}
+				let function: JitFunction = unsafe { std::mem::transmute(execute_memory.as_ptr()) };
+				function();
+				guarded::clear_fault_recovery();
+				if let Some(offset) = guarded::take_out_of_bounds() {
+					self.context.error = Some(RuntimeError::PointerOutOfBounds(offset));
+				}
+				Ok(())
+			},
+			Err(err) => Err(RuntimeError::Recompiler(err)),
+		};
+		let mut context = *self.context;
+		let result = result.and(context.io.flush_output());
+		if self.options.verbose {
+			println!("\nINFO: Memory after running:\n{:?}", context.memory);
+		}
+		let status = match (context.error, result) {
+			(Some(err), _) | (None, Err(err)) => RunStatus::Error(err),
+			(None, Ok(())) => RunStatus::Finished,
+		};
+		RunResult { status, memory: context.memory, pointer: context.pointer, steps: None }
+	}
+
+	fn disassembly(&self) -> Option<String> {
+		Some(self.listing())
+	}
+}
+/// Defines the trampoline functions that the recompiled code calls, and the type of the recompiled code,
+/// with the calling convention of the backend.
+macro_rules! trampolines {
+	($abi:literal) => {
+		type JitFunction = extern $abi fn();
+
+		impl<T: bf_memory::BfMemory + std::fmt::Debug, R: Read, W: Write> BfRecompiler<T, R, W> {
+			/// Returns a null reference after storing the error, if the memory can not access the value at index.
+			extern $abi fn get_ref(context: &mut JitContext<T, R, W>, index: i32) -> *mut T::Cell {
+				match context.memory.try_get_ref(index) {
+					Ok(value) => value,
+					Err(err) => {
+						context.error = Some(err);
+						std::ptr::null_mut()
+					},
+				}
+			}
+
+			/// Moves the index stored in current by move_value, with BfMemory::move_pointer,
+			/// and returns a reference to the value at the new index.
+			/// Like get_ref, a null reference is returned after storing the error, if the pointer can not move,
+			/// and the index in current is left unchanged.
+			extern $abi fn move_ref(context: &mut JitContext<T, R, W>, move_value: i32, current: &mut usize) -> *mut T::Cell {
+				let moved = context.memory.move_pointer(*current as i32, move_value);
+				match moved.and_then(|index| context.memory.try_get_ref(index).map(|value| (index, value as *mut T::Cell))) {
+					Ok((index, value)) => {
+						*current = index as u32 as usize;
+						value
+					},
+					Err(err) => {
+						context.error = Some(err);
+						std::ptr::null_mut()
+					},
+				}
+			}
+
+			/// Fetches the value at offset from the index stored in current, and replaces the index in current
+			/// with a reference to the current value, as fetching the value may reallocate the memory.
+			/// Like get_ref, a null reference is returned if the value at offset can not be accessed.
+			extern $abi fn get_offset_ref(context: &mut JitContext<T, R, W>, offset: i32, current: &mut usize) -> *mut T::Cell {
+				let index = *current as i32;
+				let target = match context.memory.try_get_ref(index + offset) {
+					Ok(target) => target as *mut T::Cell,
+					Err(err) => {
+						context.error = Some(err);
+						std::ptr::null_mut()
+					},
+				};
+				// The current value is already allocated, so fetching it does not move the target value.
+				*current = context.memory.get_ref(index) as *mut T::Cell as usize;
+				target
+			}
+
+			extern $abi fn print_u8(context: &mut JitContext<T, R, W>, value: u8) -> u32 {
+				match context.io.print_char(value) {
+					Ok(()) => 0,
+					Err(err) => context.store_error(err),
+				}
+			}
+
+			extern $abi fn flush_output(context: &mut JitContext<T, R, W>) -> u32 {
+				match context.io.flush_output() {
+					Ok(()) => 0,
+					Err(err) => context.store_error(err),
+				}
+			}
+
+			extern $abi fn refuel(context: &mut JitContext<T, R, W>) -> u32 {
+				context.loop_steps += context.last_fuel;
+				let loop_steps = context.loop_steps;
+				match context.limits.as_mut().map_or(Ok(()), |limits| limits.check(loop_steps)) {
+					Ok(()) => {
+						context.set_fuel_counter();
+						0
+					},
+					Err(err) => context.store_error(err),
+				}
+			}
+
+			/// Reads input into cell, which is the current value in memory.
+			extern $abi fn fetch_input(context: &mut JitContext<T, R, W>, cell: &mut T::Cell) -> u32 {
+				match context.io.get_char(*cell) {
+					Ok(value) => {
+						*cell = value;
+						0
+					},
+					Err(err) => context.store_error(err),
+				}
+			}
+
+			/// Prints the debug dump of the memory, with the memory pointer at index.
+			extern $abi fn debug_dump(context: &mut JitContext<T, R, W>, index: i32) -> u32 {
+				match context.io.flush_output() {
+					Ok(()) => {
+						print_debug_dump(&context.memory, index);
+						0
+					},
+					Err(err) => context.store_error(err),
+				}
+			}
+
+			/// Stores a CellOverflow error, for the operation at source_offset in the code.
+			extern $abi fn cell_overflow(context: &mut JitContext<T, R, W>, source_offset: u32) -> u32 {
+				let position = SourcePosition::from_byte_offset(&context.code, source_offset as usize);
+				context.store_error(RuntimeError::CellOverflow(position))
+			}
+		}
+	};
+}
+#[cfg(target_arch = "aarch64")]
+trampolines!("C");
+#[cfg(not(target_arch = "aarch64"))]
+trampolines!("sysv64");
+
+impl<T: bf_memory::BfMemory + std::fmt::Debug, R: Read, W: Write> BfRecompiler<T, R, W> {
+	/// Maps offsets in the recompiled machine code to the source code they were created from.
+	pub fn source_map(&self) -> &SourceMap {
+		&self.source_map
+	}
+
+	/// Annotated listing of the recompiled machine code, see disassembler::listing.
+	fn listing(&self) -> String {
+		disassembler::listing(&self.recompiled_memory, &self.operations, &self.source_map, self.fault_recovery_offset)
+	}
+
+	fn create_exec_memory(&self) -> Result<Mmap, BFRecompilerError> {
+		let size = ((self.recompiled_memory.len() / PAGE_SIZE) + 1) * PAGE_SIZE;
+		let mut mmap = MmapOptions::new().len(size).map_anon().map_err(BFRecompilerError::MMapCreateError)?;
+		let mut operand_iterator = self.recompiled_memory.iter();
+		mmap.fill_with(|| *operand_iterator.next().unwrap_or(&0));
+		let mmap_exec = mmap.make_exec().map_err(BFRecompilerError::MMakeExecError)?;
+		#[cfg(target_arch = "aarch64")]
+		aarch64::flush_instruction_cache(&mmap_exec[..self.recompiled_memory.len()]);
+		Ok(mmap_exec)
+	}
+}
+
+#[derive(Debug)]
+pub enum BFRecompilerError {
+	MMapCreateError(std::io::Error),
+	MMakeExecError(std::io::Error),
+}
+impl std::error::Error for BFRecompilerError {}
+impl std::fmt::Display for BFRecompilerError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		use BFRecompilerError::*;
+		match self {
+			MMapCreateError(err) => write!(f, "Error creating jit memory: {}", err),
+			MMakeExecError(err) => write!(f, "Error setting executable bit on jit memory: {}", err),
+		}
+	}
+}
@@ -0,0 +1,339 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::marker::PhantomData;
+
+use super::{Backend, JitEnvironment, RecompiledOps};
+use crate::{
+	bf_memory::BfMemory,
+	cell::{BfCell, WrappingInteger},
+	executors::{OverflowPolicy, OUTPUT_BUFFER_SIZE},
+};
+
+/// Emits x86-64 machine code, using the sysv64 calling convention for calls to the trampoline functions.
+/// The memory type T provides the machine code for moving the memory pointer.
+///
+/// "dl", "dx" or "edx" register stores value of the currently pointed to value, depending on the size of the cells.
+/// "ecx" register stores the current index.
+/// "rax" register points to the last used position in memory.
+pub(crate) struct X86_64Backend<T> {
+	memory: PhantomData<T>,
+}
+impl<T: BfMemory> Backend<T::Cell> for X86_64Backend<T> {
+	fn add_entry(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps) {
+		// First argument for get_ref, the context.
+		recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		recompiled_memory.push_opcodes(&environment.context_addr); // context_addr as argument for movabs rdi.
+
+		// Second argument for get_ref, the index.
+		recompiled_memory.push_opcodes(&[0x48, 0xbe]); // movabs rsi.
+		recompiled_memory.push_opcodes(&0isize.to_ne_bytes()); // argument for movabs rsi.
+
+		// Get initial value of "dl"
+		recompiled_memory.add_fn_call(environment.get_ref_fn_addr);
+
+		// Move returned value into "dl" register, from [rax].
+		recompiled_memory.add_load_current::<T::Cell>();
+		// Set register "ecx" to zero.
+		recompiled_memory.push_opcodes(&[0xb9, 0, 0, 0, 0]);
+	}
+
+	/// Machine code that puts "dl" back into memory, stores the index from "ecx"
+	/// in the JitContext, and returns from the recompiled code.
+	fn exit_ops(environment: &JitEnvironment) -> RecompiledOps {
+		let mut exit_ops = RecompiledOps::default();
+		exit_ops.add_store_current::<T::Cell>();
+		exit_ops.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		exit_ops.push_opcodes(&environment.pointer_addr.to_ne_bytes()); // pointer_addr as argument for movabs rdi.
+		exit_ops.push_opcodes(&[0x89, 0x0f]); // mov [rdi], ecx
+		exit_ops.push(0xc3); // return
+		exit_ops
+	}
+
+	fn add_mod(
+		environment: &JitEnvironment, offset: i32, value: <T::Cell as BfCell>::Signed, source_offset: usize,
+		recompiled_memory: &mut RecompiledOps,
+	) {
+		if offset != 0 {
+			X86_64Backend::<T>::add_offset_ref(environment, offset, recompiled_memory);
+		}
+		if environment.overflow == OverflowPolicy::Wrapping {
+			X86_64Backend::<T>::add_arithmetic(offset, 0, T::Cell::from_signed(value).into(), recompiled_memory); // add
+			return;
+		}
+
+		// Positive values are added and negative values are subtracted, so the carry flag is set on overflow.
+		let value: i32 = value.into();
+		let (operation, inverse_operation) = if value >= 0 { (0, 5) } else { (5, 0) }; // add and sub
+		X86_64Backend::<T>::add_arithmetic(offset, operation, value.unsigned_abs(), recompiled_memory);
+
+		let mut overflow_block = RecompiledOps::default();
+		match environment.overflow {
+			OverflowPolicy::Saturating => {
+				let limit = if value >= 0 { T::Cell::MAX } else { T::Cell::ZERO };
+				X86_64Backend::<T>::add_mov(offset, limit, &mut overflow_block);
+			},
+			_ => {
+				// Undo the operation, so the value is not changed by the overflowing operation.
+				X86_64Backend::<T>::add_arithmetic(offset, inverse_operation, value.unsigned_abs(), &mut overflow_block);
+				X86_64Backend::<T>::add_cell_overflow_call(environment, source_offset, &mut overflow_block);
+			},
+		}
+		recompiled_memory.push_opcodes(&[0x73, overflow_block.len() as u8]); // Jump not carry, over the overflow block.
+		recompiled_memory.push_opcodes(&overflow_block);
+	}
+
+	fn add_move(environment: &JitEnvironment, move_value: i32, recompiled_memory: &mut RecompiledOps) {
+		// Put value of "dl" back into its position in bf_memory.
+		recompiled_memory.add_store_current::<T::Cell>();
+
+		let move_ops = T::get_move_ops(environment.context_addr, environment.memory_addr, environment.move_ref_fn_addr, move_value);
+		recompiled_memory.push_opcodes(move_ops.as_ref());
+		X86_64Backend::<T>::add_null_check(environment, &[0x48, 0x85, 0xc0], recompiled_memory); // test rax, rax
+
+		// Move returned value into "dl" register, from [rax].
+		recompiled_memory.add_load_current::<T::Cell>();
+	}
+
+	fn add_loop(_environment: &JitEnvironment, loop_block: RecompiledOps, recompiled_memory: &mut RecompiledOps) -> usize {
+		let block_size = loop_block.len() as i32;
+
+		let mut compare = RecompiledOps::default();
+		compare.add_compare_current_zero::<T::Cell>();
+		recompiled_memory.push_opcodes(&compare);
+
+		// Add forward jump
+		recompiled_memory.push_opcodes(&[0x0f, 0x84]); // Jump equal
+		recompiled_memory.push_opcodes(&(block_size + 5).to_ne_bytes());
+
+		// Add loop_block
+		let block_offset = recompiled_memory.len();
+		recompiled_memory.push_opcodes(&loop_block);
+
+		// Add backwards jump
+		recompiled_memory.push_opcodes(&[0xe9]); // Jump
+		recompiled_memory.push_opcodes(&(-block_size - 5 - 6 - compare.len() as i32).to_ne_bytes());
+
+		block_offset
+	}
+
+	fn add_set(environment: &JitEnvironment, offset: i32, value: T::Cell, recompiled_memory: &mut RecompiledOps) {
+		if offset != 0 {
+			X86_64Backend::<T>::add_offset_ref(environment, offset, recompiled_memory);
+		}
+		X86_64Backend::<T>::add_mov(offset, value, recompiled_memory);
+	}
+
+	fn add_get_input(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps) {
+		// fetch_input reads into the current value in memory.
+		recompiled_memory.add_store_current::<T::Cell>();
+		recompiled_memory.push(0x50); // Push rax
+		recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		recompiled_memory.push_opcodes(&environment.context_addr); // context_addr as argument for movabs rdi.
+		recompiled_memory.push_opcodes(&[0x48, 0x89, 0xc6]); // mov rsi, rax
+		recompiled_memory.add_fn_call(environment.fetch_input_fn_addr);
+		recompiled_memory.add_error_check(&X86_64Backend::<T>::exit_ops(environment));
+		recompiled_memory.add_load_current::<T::Cell>();
+	}
+
+	fn add_print(environment: &JitEnvironment, offset: i32, recompiled_memory: &mut RecompiledOps) {
+		// The printed value is put in "r8b", only the lowest byte of the value is printed.
+		if offset == 0 {
+			recompiled_memory.push_opcodes(&[0x44, 0x0f, 0xb6, 0xc2]); // movzx r8d, dl
+		}
+		else {
+			X86_64Backend::<T>::add_offset_ref(environment, offset, recompiled_memory);
+			recompiled_memory.push_opcodes(&[0x44, 0x0f, 0xb6, 0x06]); // movzx r8d, byte [rsi]
+		}
+		match environment.output_buffer_addr {
+			Some(output_buffer_addr) => X86_64Backend::<T>::add_buffered_print(environment, output_buffer_addr, recompiled_memory),
+			None => {
+				recompiled_memory.push(0x50); // Push rax
+				recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+				recompiled_memory.push_opcodes(&environment.context_addr); // context_addr as argument for movabs rdi.
+				recompiled_memory.push_opcodes(&[0x44, 0x89, 0xc6]); // mov esi, r8d
+				recompiled_memory.add_fn_call(environment.print_u8_fn_addr);
+				recompiled_memory.add_error_check(&X86_64Backend::<T>::exit_ops(environment));
+			},
+		}
+	}
+
+	fn add_mul_add(
+		environment: &JitEnvironment, offset: i32, factor: <T::Cell as BfCell>::Signed, source_offset: usize,
+		recompiled_memory: &mut RecompiledOps,
+	) {
+		X86_64Backend::<T>::add_offset_ref(environment, offset, recompiled_memory);
+		match T::Cell::SIZE {
+			1 => recompiled_memory.push_opcodes(&[0x0f, 0xb6, 0xfa]), // movzx edi, dl
+			2 => recompiled_memory.push_opcodes(&[0x0f, 0xb7, 0xfa]), // movzx edi, dx
+			_ => recompiled_memory.push_opcodes(&[0x89, 0xd7]),       // mov edi, edx
+		}
+		if environment.overflow == OverflowPolicy::Wrapping {
+			recompiled_memory.push_opcodes(&[0x69, 0xff]); // imul edi, edi
+			recompiled_memory.push_opcodes(&Into::<i32>::into(factor).to_ne_bytes()); // Argument for imul.
+			recompiled_memory.push_sized::<T::Cell>(&[0x40, 0x00, 0x3e], &[0x01, 0x3e]); // add [rsi], dil
+			return;
+		}
+
+		// The result is calculated in 64 bits, where it can not overflow.
+		recompiled_memory.push_opcodes(&[0x48, 0x69, 0xff]); // imul rdi, rdi
+		recompiled_memory.push_opcodes(&Into::<i32>::into(factor).to_ne_bytes()); // Argument for imul.
+		match T::Cell::SIZE {
+			1 => recompiled_memory.push_opcodes(&[0x44, 0x0f, 0xb6, 0x06]), // movzx r8d, byte [rsi]
+			2 => recompiled_memory.push_opcodes(&[0x44, 0x0f, 0xb7, 0x06]), // movzx r8d, word [rsi]
+			_ => recompiled_memory.push_opcodes(&[0x44, 0x8b, 0x06]),       // mov r8d, [rsi]
+		}
+		recompiled_memory.push_opcodes(&[0x4c, 0x01, 0xc7]); // add rdi, r8
+		recompiled_memory.push_opcodes(&[0x41, 0xb8]); // mov r8d
+		recompiled_memory.push_opcodes(&Into::<u32>::into(T::Cell::MAX).to_ne_bytes()); // Argument for mov r8d.
+		recompiled_memory.push_opcodes(&[0x4c, 0x39, 0xc7]); // cmp rdi, r8. Negative results are above as well.
+
+		let mut overflow_block = RecompiledOps::default();
+		match environment.overflow {
+			OverflowPolicy::Saturating => {
+				overflow_block.push_opcodes(&[0x48, 0xc1, 0xff, 0x3f]); // sar rdi, 63. -1 for negative results, otherwise 0.
+				overflow_block.push_opcodes(&[0x48, 0xf7, 0xd7]); // not rdi. 0 for negative results, otherwise all bits set.
+			},
+			_ => X86_64Backend::<T>::add_cell_overflow_call(environment, source_offset, &mut overflow_block),
+		}
+		recompiled_memory.push_opcodes(&[0x76, overflow_block.len() as u8]); // Jump below or equal, over the overflow block.
+		recompiled_memory.push_opcodes(&overflow_block);
+		recompiled_memory.push_sized::<T::Cell>(&[0x40, 0x88, 0x3e], &[0x89, 0x3e]); // mov [rsi], dil
+	}
+
+	fn add_scan_zero(environment: &JitEnvironment, stride: i32, recompiled_memory: &mut RecompiledOps) {
+		let scan_zero_ops = T::get_scan_zero_ops(environment.context_addr, environment.memory_addr, environment.move_ref_fn_addr, stride);
+		recompiled_memory.push_opcodes(&scan_zero_ops);
+		X86_64Backend::<T>::add_null_check(environment, &[0x48, 0x85, 0xc0], recompiled_memory); // test rax, rax
+	}
+
+	fn add_debug_dump(environment: &JitEnvironment, recompiled_memory: &mut RecompiledOps) {
+		recompiled_memory.add_store_current::<T::Cell>();
+		recompiled_memory.push(0x50); // Push rax
+		recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		recompiled_memory.push_opcodes(&environment.context_addr); // context_addr as argument for movabs rdi.
+		recompiled_memory.push_opcodes(&[0x89, 0xce]); // mov esi, ecx. Second argument, the index.
+		recompiled_memory.add_fn_call(environment.debug_dump_fn_addr);
+		recompiled_memory.add_error_check(&X86_64Backend::<T>::exit_ops(environment));
+	}
+
+	/// Decrements the fuel counter, and calls refuel when it reaches zero.
+	fn add_fuel_check(environment: &JitEnvironment, fuel_counter_addr: usize, recompiled_memory: &mut RecompiledOps) {
+		recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		recompiled_memory.push_opcodes(&fuel_counter_addr.to_ne_bytes()); // argument for movabs rdi.
+		recompiled_memory.push_opcodes(&[0x48, 0xff, 0x0f]); // dec qword [rdi]
+
+		let mut refuel_block = RecompiledOps::default();
+		refuel_block.push(0x50); // Push rax
+		refuel_block.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		refuel_block.push_opcodes(&environment.context_addr); // context_addr as argument for movabs rdi.
+		refuel_block.add_fn_call(environment.refuel_fn_addr);
+		refuel_block.add_error_check(&X86_64Backend::<T>::exit_ops(environment));
+
+		recompiled_memory.push_opcodes(&[0x75, refuel_block.len() as u8]); // Jump not equal, over the refuel block.
+		recompiled_memory.push_opcodes(&refuel_block);
+	}
+
+	fn fault_recovery_ops(environment: &JitEnvironment) -> Option<RecompiledOps> {
+		// "rax" points into a guard region, so "dl" is not put back into memory.
+		Some(X86_64Backend::<T>::exit_without_store_ops(environment))
+	}
+}
+impl<T: BfMemory> X86_64Backend<T> {
+	/// Machine code that stores the index from "ecx" in the JitContext, and returns from the recompiled code,
+	/// without putting "dl" back into memory.
+	fn exit_without_store_ops(environment: &JitEnvironment) -> RecompiledOps {
+		let mut exit_ops = RecompiledOps::default();
+		exit_ops.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		exit_ops.push_opcodes(&environment.pointer_addr.to_ne_bytes()); // pointer_addr as argument for movabs rdi.
+		exit_ops.push_opcodes(&[0x89, 0x0f]); // mov [rdi], ecx
+		exit_ops.push(0xc3); // return
+		exit_ops
+	}
+
+	/// Exits from the recompiled code if test_op finds a null reference, which memories with checked access
+	/// return after storing an error. "dl" is already in memory, when the memory returns a null reference.
+	fn add_null_check(environment: &JitEnvironment, test_op: &[u8], recompiled_memory: &mut RecompiledOps) {
+		if !environment.checked_access {
+			return;
+		}
+		let exit_ops = X86_64Backend::<T>::exit_without_store_ops(environment);
+		recompiled_memory.push_opcodes(test_op);
+		recompiled_memory.push_opcodes(&[0x75, exit_ops.len() as u8]); // Jump not equal, over the exit_ops.
+		recompiled_memory.push_opcodes(&exit_ops);
+	}
+
+	/// Applies an arithmetic instruction with an immediate value, to "dl" when offset is 0, otherwise to [rsi].
+	/// operation is the opcode extension of the instruction, 0 for add and 5 for sub.
+	fn add_arithmetic(offset: i32, operation: u8, value: u32, recompiled_memory: &mut RecompiledOps) {
+		let modrm = if offset == 0 { 0xc2 | operation << 3 } else { 0x06 | operation << 3 };
+		recompiled_memory.push_sized::<T::Cell>(&[0x80, modrm], &[0x81, modrm]); // add dl / add byte [rsi]
+		recompiled_memory.push_opcodes(&value.to_ne_bytes()[..T::Cell::SIZE]); // Argument for the instruction.
+	}
+
+	/// Sets "dl" to value when offset is 0, otherwise sets [rsi] to value.
+	fn add_mov(offset: i32, value: T::Cell, recompiled_memory: &mut RecompiledOps) {
+		if offset == 0 {
+			recompiled_memory.push_sized::<T::Cell>(&[0xb2], &[0xba]); // mov dl
+		}
+		else {
+			recompiled_memory.push_sized::<T::Cell>(&[0xc6, 0x06], &[0xc7, 0x06]); // mov byte [rsi]
+		}
+		recompiled_memory.push_cell(value); // Argument for mov.
+	}
+
+	/// Calls cell_overflow, which stores the error, and exits from the recompiled code.
+	fn add_cell_overflow_call(environment: &JitEnvironment, source_offset: usize, recompiled_memory: &mut RecompiledOps) {
+		recompiled_memory.push(0x50); // Push rax
+		recompiled_memory.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		recompiled_memory.push_opcodes(&environment.context_addr); // context_addr as argument for movabs rdi.
+		recompiled_memory.push(0xbe); // mov esi
+		recompiled_memory.push_opcodes(&(source_offset as u32).to_ne_bytes()); // Argument for mov esi.
+		recompiled_memory.add_fn_call(environment.cell_overflow_fn_addr);
+		recompiled_memory.add_error_check(&X86_64Backend::<T>::exit_ops(environment));
+	}
+
+	/// Puts a reference to the value at offset from the current index into "rsi".
+	fn add_offset_ref(environment: &JitEnvironment, offset: i32, recompiled_memory: &mut RecompiledOps) {
+		let offset_ref_ops =
+			T::get_offset_ref_ops(environment.context_addr, environment.memory_addr, environment.get_offset_ref_fn_addr, offset);
+		recompiled_memory.push_opcodes(&offset_ref_ops);
+		X86_64Backend::<T>::add_null_check(environment, &[0x48, 0x85, 0xf6], recompiled_memory); // test rsi, rsi
+	}
+
+	/// Appends "r8b" to the output buffer, only calling flush_output when the buffer is full.
+	fn add_buffered_print(environment: &JitEnvironment, output_buffer_addr: usize, recompiled_memory: &mut RecompiledOps) {
+		recompiled_memory.push_opcodes(&[0x48, 0xbe]); // movabs rsi.
+		recompiled_memory.push_opcodes(&output_buffer_addr.to_ne_bytes()); // argument for movabs rsi.
+		recompiled_memory.push_opcodes(&[0x48, 0x8b, 0x3e]); // mov rdi, [rsi]. Length of the buffer.
+		recompiled_memory.push_opcodes(&[0x44, 0x88, 0x44, 0x3e, 0x08]); // mov [rsi + rdi + 8], r8b. Store after the length field.
+		recompiled_memory.push_opcodes(&[0x48, 0xff, 0xc7]); // inc rdi
+		recompiled_memory.push_opcodes(&[0x48, 0x89, 0x3e]); // mov [rsi], rdi
+		recompiled_memory.push_opcodes(&[0x48, 0x81, 0xff]); // cmp rdi
+		recompiled_memory.push_opcodes(&(OUTPUT_BUFFER_SIZE as u32).to_ne_bytes()); // argument for cmp rdi.
+
+		let mut flush_block = RecompiledOps::default();
+		flush_block.push(0x50); // Push rax
+		flush_block.push_opcodes(&[0x48, 0xbf]); // movabs rdi.
+		flush_block.push_opcodes(&environment.context_addr); // context_addr as argument for movabs rdi.
+		flush_block.add_fn_call(environment.flush_output_fn_addr);
+		flush_block.add_error_check(&X86_64Backend::<T>::exit_ops(environment));
+
+		recompiled_memory.push_opcodes(&[0x75, flush_block.len() as u8]); // Jump not equal, over the flush block.
+		recompiled_memory.push_opcodes(&flush_block);
+	}
+}
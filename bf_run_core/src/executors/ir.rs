@@ -0,0 +1,257 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{fmt::Write, str::FromStr};
+
+use super::operations::{Operation, Operations, SourceSpan};
+use crate::cell::BfCell;
+
+/// What the textual form of the operations includes, besides the operations themselves.
+/// Each operation is on its own line, with the operations of a loop indented by a tab:
+/// "mod +3", "move -2", "set 0", "input", "print", "muladd +2 @1", "scan -1", "dump", and "loop {" ... "}".
+/// Operations on a value at an offset from the current position end with "@offset".
+/// Spans and statistics are added as comments, starting with ';'.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IrFormat {
+	/// Adds the span of each operation.
+	pub spans:      bool,
+	/// Adds the number of operations, the number of nested loops,
+	/// and the movement of the pointer per iteration to each loop.
+	pub loop_stats: bool,
+}
+
+impl<C: BfCell> Operations<C> {
+	/// Creates the textual form of the operations, which can be parsed back with from_str.
+	pub fn to_ir(&self, format: &IrFormat) -> String {
+		let mut ir = String::new();
+		write_operations(self, format, 0, &mut ir);
+		ir
+	}
+}
+
+fn write_operations<C: BfCell>(operations: &Operations<C>, format: &IrFormat, depth: usize, ir: &mut String) {
+	for operation in operations.iter() {
+		let mut comments = Vec::new();
+		if format.spans {
+			comments.push(format!("{:?}", operation.span()));
+		}
+		if let (Operation::Loop(operations, _), true) = (operation, format.loop_stats) {
+			let stats = LoopStats::new(operations);
+			comments.push(format!("{} operations", stats.operations));
+			comments.push(format!("{} nested loops", stats.loops));
+			match stats.shift {
+				Some(shift) => comments.push(format!("shift {:+}", shift)),
+				None => comments.push("shift unknown".to_string()),
+			}
+		}
+
+		ir.push_str(&"\t".repeat(depth));
+		ir.push_str(&statement(operation));
+		if let Operation::Loop(..) = operation {
+			ir.push_str(" {");
+		}
+		if !comments.is_empty() {
+			write!(ir, " ; {}", comments.join(", ")).unwrap();
+		}
+		ir.push('\n');
+
+		if let Operation::Loop(operations, _) = operation {
+			write_operations(operations, format, depth + 1, ir);
+			ir.push_str(&"\t".repeat(depth));
+			ir.push_str("}\n");
+		}
+	}
+}
+
+/// The textual form of a single operation, without its span.
+/// Loops are only named, without their operations.
+pub(crate) fn statement<C: BfCell>(operation: &Operation<C>) -> String {
+	fn at(offset: i32) -> String {
+		match offset {
+			0 => String::new(),
+			offset => format!(" @{}", offset),
+		}
+	}
+	match operation {
+		Operation::Mod { offset, value, .. } => format!("mod {:+}{}", value, at(*offset)),
+		Operation::Move(move_value, _) => format!("move {:+}", move_value),
+		Operation::Loop(..) => "loop".to_string(),
+		Operation::Set { offset, value, .. } => format!("set {}{}", value, at(*offset)),
+		Operation::GetInput(_) => "input".to_string(),
+		Operation::Print { offset, .. } => format!("print{}", at(*offset)),
+		Operation::MulAdd { offset, factor, .. } => format!("muladd {:+}{}", factor, at(*offset)),
+		Operation::ScanZero(stride, _) => format!("scan {:+}", stride),
+		Operation::DebugDump(_) => "dump".to_string(),
+	}
+}
+
+/// Statistics about the operations of a loop, including the operations of nested loops.
+struct LoopStats {
+	operations: usize,
+	loops:      usize,
+	/// Movement of the pointer in each iteration, unknown if it depends on the values in memory.
+	shift:      Option<i32>,
+}
+impl LoopStats {
+	fn new<C: BfCell>(operations: &Operations<C>) -> LoopStats {
+		let mut stats = LoopStats { operations: 0, loops: 0, shift: Some(0) };
+		for operation in operations.iter() {
+			stats.operations += 1;
+			match operation {
+				Operation::Move(move_value, _) => stats.shift = stats.shift.map(|shift| shift.wrapping_add(*move_value)),
+				Operation::Loop(operations, _) => {
+					let nested = LoopStats::new(operations);
+					stats.operations += nested.operations;
+					stats.loops += nested.loops + 1;
+					// A nested loop runs an unknown number of times, so only a balanced nested loop keeps the shift known.
+					if nested.shift != Some(0) {
+						stats.shift = None;
+					}
+				},
+				Operation::ScanZero(..) => stats.shift = None,
+				_ => (),
+			}
+		}
+		stats
+	}
+}
+
+impl<C: BfCell> std::fmt::Display for Operations<C> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&self.to_ir(&IrFormat::default()))
+	}
+}
+
+/// Parses the textual form created by to_ir.
+/// Comments are ignored, the span of each parsed operation is its position in the textual form.
+impl<C: BfCell> FromStr for Operations<C> {
+	type Err = IrParseError;
+
+	fn from_str(ir: &str) -> Result<Operations<C>, IrParseError> {
+		// Operations of the loops that are being parsed, with the span of their "loop {" line.
+		let mut loops: Vec<(Operations<C>, SourceSpan)> = Vec::new();
+		let mut operations = Operations::default();
+		let mut line_start = 0;
+		for (index, line) in ir.split('\n').enumerate() {
+			let line_number = index + 1;
+			let code = line.split(';').next().unwrap_or_default();
+			let trimmed = code.trim();
+			let start = line_start + (code.len() - code.trim_start().len());
+			let column = code[..start - line_start].chars().count() + 1;
+			let span = SourceSpan { start, end: start + trimmed.len(), line: line_number, column };
+			line_start += line.len() + 1;
+
+			let error = |kind| IrParseError { kind, line: line_number };
+			let mut words = trimmed.split_whitespace();
+			let name = match words.next() {
+				Some(name) => name,
+				None => continue,
+			};
+			let arguments: Vec<&str> = words.collect();
+			let operation = match (name, arguments.as_slice()) {
+				("loop", ["{"]) => {
+					loops.push((std::mem::take(&mut operations), span));
+					continue;
+				},
+				("}", []) => {
+					let (outer_operations, loop_span) = loops.pop().ok_or_else(|| error(IrParseErrorKind::UnmatchedLoopEnd))?;
+					let loop_operations = std::mem::replace(&mut operations, outer_operations);
+					Operation::Loop(loop_operations, SourceSpan { end: span.end, ..loop_span })
+				},
+				_ => parse_operation(name, &arguments, span).ok_or_else(|| match OPERATION_NAMES.contains(&name) {
+					true => error(IrParseErrorKind::InvalidArguments),
+					false => error(IrParseErrorKind::UnknownOperation),
+				})?,
+			};
+			operations.push(operation);
+		}
+		match loops.pop() {
+			Some((_, loop_span)) => Err(IrParseError { kind: IrParseErrorKind::UnterminatedLoop, line: loop_span.line }),
+			None => Ok(operations),
+		}
+	}
+}
+
+/// Names of the operations in the textual form, and the end of a loop.
+const OPERATION_NAMES: [&str; 10] = ["mod", "move", "loop", "}", "set", "input", "print", "muladd", "scan", "dump"];
+
+/// Parses an operation other than a loop, None if the arguments do not fit the operation.
+fn parse_operation<C: BfCell>(name: &str, arguments: &[&str], span: SourceSpan) -> Option<Operation<C>> {
+	let operation = match (name, arguments) {
+		("mod", [value, offset @ ..]) => Operation::Mod { offset: parse_offset(offset)?, value: parse(value)?, span },
+		("move", [value]) => Operation::Move(parse(value)?, span),
+		("set", [value, offset @ ..]) => Operation::Set { offset: parse_offset(offset)?, value: parse(value)?, span },
+		("input", []) => Operation::GetInput(span),
+		("print", offset) => Operation::Print { offset: parse_offset(offset)?, span },
+		("muladd", [factor, offset @ ..]) => Operation::MulAdd { offset: parse_offset(offset)?, factor: parse(factor)?, span },
+		("scan", [stride]) => Operation::ScanZero(parse(stride)?, span),
+		("dump", []) => Operation::DebugDump(span),
+		_ => return None,
+	};
+	Some(operation)
+}
+
+fn parse<T: FromStr>(argument: &str) -> Option<T> {
+	argument.parse().ok()
+}
+
+/// Parses the optional "@offset" after the other arguments of an operation.
+fn parse_offset(arguments: &[&str]) -> Option<i32> {
+	match arguments {
+		[] => Some(0),
+		[offset] => parse(offset.strip_prefix('@')?),
+		_ => None,
+	}
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum IrParseErrorKind {
+	UnknownOperation,
+	/// The arguments are missing, superfluous, or not a number that fits the operation.
+	InvalidArguments,
+	UnmatchedLoopEnd,
+	UnterminatedLoop,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct IrParseError {
+	kind: IrParseErrorKind,
+	line: usize,
+}
+impl IrParseError {
+	pub fn kind(&self) -> IrParseErrorKind {
+		self.kind
+	}
+
+	/// Line of the offending operation, counted from 1.
+	/// For unterminated loops, this is the line that starts the loop.
+	pub fn line(&self) -> usize {
+		self.line
+	}
+}
+impl std::error::Error for IrParseError {}
+impl std::fmt::Display for IrParseError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let description = match self.kind {
+			IrParseErrorKind::UnknownOperation => "Unknown operation",
+			IrParseErrorKind::InvalidArguments => "Invalid arguments",
+			IrParseErrorKind::UnmatchedLoopEnd => "'}' without a matching loop",
+			IrParseErrorKind::UnterminatedLoop => "Loop without a matching '}'",
+		};
+		write!(f, "{} on line {}", description, self.line)
+	}
+}
@@ -15,32 +15,326 @@
 	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use crate::bf_memory::BfMemory;
+use std::{
+	io::{stdin, stdout, ErrorKind, Read, Stdin, Stdout, Write},
+	time::{Duration, Instant},
+};
 
-pub(crate) fn get_char() -> u8 {
-	use std::io::{stdin, Read};
-	let stdin = stdin();
-	let mut lock = stdin.lock();
-	let mut buf = [0u8; 1];
-	lock.read_exact(&mut buf).unwrap();
-	buf[0]
+use crate::{
+	bf_memory::BfMemory,
+	cell::BfCell,
+	executors::operations::{ParseError, SourcePosition},
+};
+
+/// What the ',' instruction does when there is no more input.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum EofPolicy {
+	/// The current cell keeps its value.
+	#[default]
+	Unchanged,
+	/// The current cell is set to 0.
+	Zero,
+	/// The current cell is set to -1, the maximum value of the cell.
+	MinusOne,
+	/// Execution stops with RuntimeError::EndOfInput.
+	Error,
+}
+
+/// What happens when an instruction increments a cell past its maximum value, or decrements it below zero.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum OverflowPolicy {
+	/// The value wraps around to the other end of the range of the cell.
+	#[default]
+	Wrapping,
+	/// The value stays at the maximum value, or at zero.
+	Saturating,
+	/// Execution stops with RuntimeError::CellOverflow.
+	Trapping,
+}
+
+pub(crate) const OUTPUT_BUFFER_SIZE: usize = 4096;
+
+/// Output that has not yet been written to the output handle.
+/// The layout is fixed, since recompiled code appends to the buffer directly.
+#[repr(C)]
+pub(crate) struct OutputBuffer {
+	pub(crate) len:  usize,
+	pub(crate) data: [u8; OUTPUT_BUFFER_SIZE],
+}
+impl std::fmt::Debug for OutputBuffer {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("OutputBuffer").field("data", &&self.data[..self.len]).finish()
+	}
+}
+
+/// The input and output handles used by the ',' and '.' instructions.
+///
+/// Any Read + Write pair can be used, for example a byte slice as input
+/// and a mutable reference to a `Vec<u8>` to capture the output.
+///
+/// Output is buffered by default, the buffer is flushed when full,
+/// before reading input and when the executor finishes.
+#[derive(Debug)]
+pub struct BfIo<R, W> {
+	input:         R,
+	output:        W,
+	eof_policy:    EofPolicy,
+	output_buffer: Option<Box<OutputBuffer>>,
+}
+impl<R: Read, W: Write> BfIo<R, W> {
+	pub fn new(input: R, output: W) -> BfIo<R, W> {
+		let output_buffer = Some(Box::new(OutputBuffer { len: 0, data: [0; OUTPUT_BUFFER_SIZE] }));
+		BfIo { input, output, eof_policy: EofPolicy::default(), output_buffer }
+	}
+
+	pub fn with_eof_policy(mut self, eof_policy: EofPolicy) -> BfIo<R, W> {
+		self.eof_policy = eof_policy;
+		self
+	}
+
+	/// Disables the output buffer, so every byte is written and flushed immediately.
+	/// Useful for interactive programs.
+	pub fn with_unbuffered_output(mut self) -> BfIo<R, W> {
+		self.output_buffer = None;
+		self
+	}
+
+	pub fn into_inner(self) -> (R, W) {
+		(self.input, self.output)
+	}
+
+	/// Reads the next input byte, current is the value of the cell being read into.
+	pub(crate) fn get_char<C: BfCell>(&mut self, current: C) -> Result<C, RuntimeError> {
+		self.flush_output()?;
+		let mut buf = [0u8; 1];
+		match self.input.read_exact(&mut buf) {
+			Ok(()) => Ok(C::from(buf[0])),
+			Err(err) if err.kind() == ErrorKind::UnexpectedEof => match self.eof_policy {
+				EofPolicy::Unchanged => Ok(current),
+				EofPolicy::Zero => Ok(C::ZERO),
+				EofPolicy::MinusOne => Ok(C::MAX),
+				EofPolicy::Error => Err(RuntimeError::EndOfInput),
+			},
+			Err(err) => Err(RuntimeError::Io(err)),
+		}
+	}
+
+	pub(crate) fn print_char(&mut self, source: u8) -> Result<(), RuntimeError> {
+		match &mut self.output_buffer {
+			Some(output_buffer) => {
+				output_buffer.data[output_buffer.len] = source;
+				output_buffer.len += 1;
+				if output_buffer.len == OUTPUT_BUFFER_SIZE {
+					self.flush_output()?;
+				}
+				Ok(())
+			},
+			None => {
+				self.output.write_all(&[source]).map_err(RuntimeError::Io)?;
+				self.output.flush().map_err(RuntimeError::Io)
+			},
+		}
+	}
+
+	/// Writes the content of the output buffer to the output handle.
+	pub(crate) fn flush_output(&mut self) -> Result<(), RuntimeError> {
+		if let Some(output_buffer) = &mut self.output_buffer {
+			if output_buffer.len > 0 {
+				let len = std::mem::replace(&mut output_buffer.len, 0);
+				self.output.write_all(&output_buffer.data[..len]).map_err(RuntimeError::Io)?;
+				self.output.flush().map_err(RuntimeError::Io)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Address of the output buffer, if output is buffered.
+	pub(crate) fn output_buffer_addr(&mut self) -> Option<usize> {
+		self.output_buffer
+			.as_mut()
+			.map(|output_buffer| output_buffer.as_mut() as *mut OutputBuffer as usize)
+	}
+}
+impl BfIo<Stdin, Stdout> {
+	/// Creates a BfIo using the stdin and stdout of the process.
+	pub fn stdio() -> BfIo<Stdin, Stdout> {
+		BfIo::new(stdin(), stdout())
+	}
+}
+
+/// Settings shared by all executors.
+#[derive(Debug, Clone)]
+pub struct ExecutorOptions {
+	/// Run the optimization passes on the operations before executing them.
+	pub enable_optimizations: bool,
+	/// Print information about the operations, and the memory after running.
+	pub verbose:              bool,
+	/// Number of steps after which execution stops with RuntimeError::FuelExhausted.
+	/// The interpreters count executed instructions, the recompiler counts loop iterations.
+	pub fuel:                 Option<u64>,
+	/// Wall-clock time after which execution stops with RuntimeError::Timeout.
+	pub timeout:              Option<Duration>,
+	/// Arithmetic used when a cell is modified beyond its range.
+	pub overflow:             OverflowPolicy,
+	/// Execute the '#' instruction, which prints the memory pointer and the first values of the memory to stderr.
+	pub debug_dump:           bool,
+}
+impl Default for ExecutorOptions {
+	fn default() -> ExecutorOptions {
+		ExecutorOptions {
+			enable_optimizations: true,
+			verbose:              false,
+			fuel:                 None,
+			timeout:              None,
+			overflow:             OverflowPolicy::Wrapping,
+			debug_dump:           false,
+		}
+	}
 }
-pub(crate) fn print_char(source: u8) {
-	use std::io::{stdout, Write};
-	print!("{}", source as char);
-	let mut stdout = stdout();
-	stdout.flush().unwrap();
+
+/// Number of values from index 0 that the '#' instruction prints.
+const DEBUG_DUMP_VALUES: i32 = 10;
+
+/// Prints the memory pointer and the first values of memory to stderr, for the '#' instruction.
+/// The value at the memory pointer is enclosed in brackets.
+pub(crate) fn print_debug_dump<T: BfMemory>(memory: &T, pointer: i32) {
+	let values = (0..DEBUG_DUMP_VALUES)
+		.map(|index| {
+			let value: u32 = memory.get_value(index).into();
+			match index == pointer {
+				true => format!("[{}]", value),
+				false => value.to_string(),
+			}
+		})
+		.collect::<Vec<_>>();
+	eprintln!("# pointer: {}, values: {}", pointer, values.join(" "));
+}
+
+/// How many steps can pass between each check of the timeout.
+const LIMITS_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// Checks the fuel and timeout limits of an execution.
+#[derive(Debug)]
+pub(crate) struct Limits {
+	fuel:       Option<u64>,
+	deadline:   Option<Instant>,
+	next_check: u64,
+}
+impl Limits {
+	pub(crate) fn new(options: &ExecutorOptions) -> Limits {
+		let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+		let mut limits = Limits { fuel: options.fuel, deadline, next_check: 0 };
+		limits.update_next_check(0);
+		limits
+	}
+
+	/// Fails if steps has used all the fuel, or if the deadline has passed.
+	/// The deadline is only checked every LIMITS_CHECK_INTERVAL steps.
+	#[inline(always)]
+	pub(crate) fn check(&mut self, steps: u64) -> Result<(), RuntimeError> {
+		if steps >= self.next_check { self.check_slow(steps) } else { Ok(()) }
+	}
+
+	#[inline(never)]
+	fn check_slow(&mut self, steps: u64) -> Result<(), RuntimeError> {
+		if self.fuel.is_some_and(|fuel| steps >= fuel) {
+			return Err(RuntimeError::FuelExhausted);
+		}
+		if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+			return Err(RuntimeError::Timeout);
+		}
+		self.update_next_check(steps);
+		Ok(())
+	}
+
+	fn update_next_check(&mut self, steps: u64) {
+		self.next_check = match (self.fuel, self.deadline) {
+			(None, None) => u64::MAX,
+			(Some(fuel), None) => fuel,
+			(None, Some(_)) => steps.saturating_add(LIMITS_CHECK_INTERVAL),
+			(Some(fuel), Some(_)) => fuel.min(steps.saturating_add(LIMITS_CHECK_INTERVAL)),
+		};
+	}
+
+	/// Number of steps that can be taken from steps, before check has to be called again.
+	pub(crate) fn steps_until_check(&self, steps: u64) -> u64 {
+		self.next_check.saturating_sub(steps)
+	}
+}
+
+pub trait Executor<T: BfMemory + std::fmt::Debug, R: Read, W: Write> {
+	fn new(code: String, bf_memory: T, io: BfIo<R, W>, options: ExecutorOptions) -> Result<Self, ParseError>
+	where Self: Sized;
+	fn start(self) -> RunResult<T>;
+	/// Annotated listing of the machine code created for the program,
+	/// None for executors that do not create machine code.
+	fn disassembly(&self) -> Option<String> {
+		None
+	}
+}
+
+/// The state of a brainfuck program after an executor stopped running it.
+#[derive(Debug)]
+pub struct RunResult<T> {
+	pub status:  RunStatus,
+	/// Memory of the program, the tape can be read back with BfMemory::get_value.
+	pub memory:  T,
+	/// Index of the memory pointer when the program stopped.
+	pub pointer: i32,
+	/// Number of executed operations, if the executor counts them.
+	pub steps:   Option<u64>,
+}
+impl<T: BfMemory> RunResult<T> {
+	/// Value of the cell that the memory pointer pointed to when the program stopped.
+	pub fn current_value(&self) -> T::Cell {
+		self.memory.get_value(self.pointer)
+	}
+
+	/// Values of the cells in the index range start..end.
+	pub fn tape(&self, start: i32, end: i32) -> Vec<T::Cell> {
+		(start..end).map(|index| self.memory.get_value(index)).collect()
+	}
+}
+
+#[derive(Debug)]
+pub enum RunStatus {
+	Finished,
+	Error(RuntimeError),
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+	EndOfInput,
+	Io(std::io::Error),
+	Recompiler(BFRecompilerError),
+	FuelExhausted,
+	Timeout,
+	/// Accessed a value at the offset from index 0, that is outside of the memory.
+	PointerOutOfBounds(isize),
+	/// A cell was modified beyond its range, by the instruction at the position, with OverflowPolicy::Trapping.
+	CellOverflow(SourcePosition),
 }
-pub trait Executor<T: BfMemory + std::fmt::Debug> {
-	fn new(code: String, bf_memory: T, enable_optimizations: bool, verbose: bool) -> Self;
-	fn start(self);
+impl std::error::Error for RuntimeError {}
+impl std::fmt::Display for RuntimeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RuntimeError::EndOfInput => write!(f, "Tried to read past the end of the input"),
+			RuntimeError::Io(err) => write!(f, "Input/output error: {}", err),
+			RuntimeError::Recompiler(err) => write!(f, "{}", err),
+			RuntimeError::FuelExhausted => write!(f, "The program ran out of fuel"),
+			RuntimeError::Timeout => write!(f, "The program exceeded its time limit"),
+			RuntimeError::PointerOutOfBounds(offset) => write!(f, "Pointer out of bounds at offset {}", offset),
+			RuntimeError::CellOverflow(position) => write!(f, "Cell overflow at {}", position),
+		}
+	}
 }
 
 pub(crate) mod bf_interpreter;
 pub(crate) mod bf_opt_interpreter;
 pub(crate) mod bf_recompiler;
+pub mod ir;
 pub mod operations;
 
 pub use bf_interpreter::BfInterpreter;
-pub use bf_opt_interpreter::BfOptInterpreter;
-pub use bf_recompiler::BfRecompiler;
+pub use bf_opt_interpreter::{BfOptInterpreter, LoopProfile};
+pub use bf_recompiler::{BFRecompilerError, BfRecompiler, SourceMap, RECOMPILER_SUPPORTED};
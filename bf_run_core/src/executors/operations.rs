@@ -17,55 +17,125 @@
 
 use std::ops::{Deref, DerefMut};
 
+use super::OverflowPolicy;
+use crate::cell::{BfCell, WrappingInteger};
+
+/// An operation on memory with values of type C.
+/// The span of an operation covers the source code it was created from,
+/// operations merged by the optimizer cover the union of the spans of the merged operations.
 #[derive(Debug, Eq, PartialEq)]
-pub enum Operation {
-	Mod(i8),
-	Move(i32),
-	Loop(Operations),
-	SetValue(u8),
-	GetInput,
-	PrintOutput,
+pub enum Operation<C: BfCell> {
+	/// Adds value to the value at offset from the current position.
+	/// Overflow errors are reported at the start of the span.
+	Mod {
+		offset: i32,
+		value:  C::Signed,
+		span:   SourceSpan,
+	},
+	Move(i32, SourceSpan),
+	/// Repeats the operations while the current value is not zero.
+	/// The span covers the source code of the loop, from its '[' to its ']'.
+	Loop(Operations<C>, SourceSpan),
+	/// Sets the value at offset from the current position.
+	Set {
+		offset: i32,
+		value:  C,
+		span:   SourceSpan,
+	},
+	GetInput(SourceSpan),
+	/// Prints the value at offset from the current position.
+	Print {
+		offset: i32,
+		span:   SourceSpan,
+	},
+	/// Adds the current value multiplied by factor, to the value at offset from the current position.
+	/// The span covers the instructions in the loop that modified the value at offset.
+	MulAdd {
+		offset: i32,
+		factor: C::Signed,
+		span:   SourceSpan,
+	},
+	/// Moves by the stride until reaching a value of zero.
+	ScanZero(i32, SourceSpan),
+	/// Prints the memory pointer and the first values of the memory to stderr, for the '#' instruction.
+	/// The operations around it are not merged, and the pointer is moved to its position before it.
+	DebugDump(SourceSpan),
+}
+impl<C: BfCell> Operation<C> {
+	/// The source code the operation was created from.
+	pub fn span(&self) -> SourceSpan {
+		match self {
+			Operation::Mod { span, .. } => *span,
+			Operation::Move(_, span) => *span,
+			Operation::Loop(_, span) => *span,
+			Operation::Set { span, .. } => *span,
+			Operation::GetInput(span) => *span,
+			Operation::Print { span, .. } => *span,
+			Operation::MulAdd { span, .. } => *span,
+			Operation::ScanZero(_, span) => *span,
+			Operation::DebugDump(span) => *span,
+		}
+	}
 }
 
-#[derive(Debug, Default, Eq, PartialEq)]
-pub struct Operations {
-	operations: Vec<Operation>,
+#[derive(Debug, Eq, PartialEq)]
+pub struct Operations<C: BfCell> {
+	operations: Vec<Operation<C>>,
 }
-impl Operations {
-	pub fn conv_string_to_operations(code: &str) -> Operations {
-		Operations::iterator_to_operations(&mut code.chars().enumerate(), None)
+impl<C: BfCell> Default for Operations<C> {
+	fn default() -> Operations<C> {
+		Operations { operations: Vec::new() }
+	}
+}
+impl<C: BfCell> Operations<C> {
+	/// Parses code into operations, '#' is parsed as Operation::DebugDump if debug_dump is set, and ignored otherwise.
+	pub fn conv_string_to_operations(code: &str, debug_dump: bool) -> Result<Operations<C>, ParseError> {
+		Operations::iterator_to_operations(code, &mut SourceCursor::new(code), None, debug_dump)
 	}
 
-	fn iterator_to_operations(iterator: &mut std::iter::Enumerate<std::str::Chars<'_>>, loop_start: Option<usize>) -> Operations {
+	fn iterator_to_operations(
+		code: &str, cursor: &mut SourceCursor<'_>, loop_start: Option<SourceSpan>, debug_dump: bool,
+	) -> Result<Operations<C>, ParseError> {
 		let mut vec = Operations::default();
 
-		while let Some((index, character)) = iterator.next() {
+		while let Some((character, span)) = cursor.next() {
 			match character {
-				'+' => vec.push(Operation::Mod(1)),
-				'-' => vec.push(Operation::Mod(-1)),
-				'<' => vec.push(Operation::Move(-1)),
-				'>' => vec.push(Operation::Move(1)),
-				'[' => vec.push(Operation::Loop(Operations::iterator_to_operations(iterator, Some(index)))),
+				'+' => vec.push(Operation::Mod { offset: 0, value: C::Signed::ONE, span }),
+				'-' => vec.push(Operation::Mod { offset: 0, value: C::Signed::ONE.wrapping_neg(), span }),
+				'<' => vec.push(Operation::Move(-1, span)),
+				'>' => vec.push(Operation::Move(1, span)),
+				'[' => {
+					let operations = Operations::iterator_to_operations(code, cursor, Some(span), debug_dump)?;
+					// The cursor is just past the ']' of the loop.
+					vec.push(Operation::Loop(operations, SourceSpan { end: cursor.offset(), ..span }));
+				},
 				']' => {
 					if loop_start.is_none() {
-						panic!("loop terminator without matching start character at index {}", index);
+						return Err(ParseError::new(ParseErrorKind::UnmatchedLoopEnd, code, span.start));
 					}
-					return vec;
+					return Ok(vec);
 				},
-				',' => vec.push(Operation::GetInput),
-				'.' => vec.push(Operation::PrintOutput),
+				',' => vec.push(Operation::GetInput(span)),
+				'.' => vec.push(Operation::Print { offset: 0, span }),
+				'#' if debug_dump => vec.push(Operation::DebugDump(span)),
 				_ => (),
 			}
 		}
 		if let Some(loop_start) = loop_start {
-			panic!("Loop started at index {} has no terminating ']' character", loop_start)
+			return Err(ParseError::new(ParseErrorKind::UnterminatedLoop, code, loop_start.start));
 		}
-		vec
+		Ok(vec)
 	}
 
-	pub fn optimize(&mut self) {
+	/// Optimizes the operations, without changing their result with the given overflow policy.
+	/// With other policies than OverflowPolicy::Wrapping, modifications are only merged when no overflow can be hidden,
+	/// and with OverflowPolicy::Trapping they are not merged at all.
+	/// With OverflowPolicy::Trapping modifications also stay at the memory pointer, and multiply loops are kept,
+	/// so the memory and the pointer are left like the unoptimized operations leave them, when an overflow stops the program.
+	pub fn optimize(&mut self, overflow: OverflowPolicy) {
 		loop {
-			let new_ops = Operations::optimise_operations(self.operations.as_slice());
+			let new_ops =
+				Operations::sink_moves(Operations::optimise_operations(self.operations.as_slice(), overflow).as_slice(), overflow);
 			if *self == new_ops {
 				break;
 			}
@@ -73,71 +143,375 @@ impl Operations {
 		}
 	}
 
-	fn optimise_operations(old_ops: &[Operation]) -> Operations {
-		let mut new_ops = Operations::default();
+	fn optimise_operations(old_ops: &[Operation<C>], overflow: OverflowPolicy) -> Operations<C> {
+		let mut new_ops = Operations::<C>::default();
 		old_ops.iter().for_each(|operation| match operation {
-			Operation::Mod(value) => {
+			Operation::Mod { offset, value, span } => {
 				let last_mut = new_ops.last_mut();
 				match last_mut {
-					Some(Operation::Mod(last)) => *last = (*last).wrapping_add(*value),
-					Some(Operation::SetValue(last)) => *last = (*last).wrapping_add((*value) as u8),
-					_ => new_ops.push(Operation::Mod(*value)),
+					Some(Operation::Mod { offset: last_offset, value: last, span: last_span })
+						if last_offset == offset && Operations::<C>::can_merge(*last, *value, overflow) =>
+					{
+						*last = (*last).wrapping_add(*value);
+						*last_span = last_span.union(*span);
+					},
+					Some(Operation::Set { offset: last_offset, value: last, span: last_span }) if last_offset == offset => {
+						match last.add_with_overflow(Into::<i32>::into(*value) as i64, overflow) {
+							Some(sum) => {
+								*last = sum;
+								*last_span = last_span.union(*span);
+							},
+							// Keep the modification, so the overflow happens when it is executed.
+							None => new_ops.push(Operation::Mod { offset: *offset, value: *value, span: *span }),
+						}
+					},
+					_ => new_ops.push(Operation::Mod { offset: *offset, value: *value, span: *span }),
 				}
 			},
-			Operation::Move(value) => {
+			Operation::Move(value, span) => {
 				let last_mut = new_ops.last_mut();
 				match last_mut {
-					Some(Operation::Move(last)) => *last += value,
-					_ => new_ops.push(Operation::Move(*value)),
+					Some(Operation::Move(last, last_span)) => {
+						*last += value;
+						*last_span = last_span.union(*span);
+					},
+					_ => new_ops.push(Operation::Move(*value, *span)),
 				}
 			},
-			Operation::Loop(operations) => {
-				if operations.len() == 1 {
-					if let Some(Operation::Mod(value)) = operations.last() {
-						if *value == 1 || *value == -1 {
-							new_ops.push(Operation::SetValue(0));
-						}
-						else {
-							let loop_ops = Operations::optimise_operations(operations.as_slice());
-							new_ops.push(Operation::Loop(loop_ops));
-						}
-					}
-					else {
-						let loop_ops = Operations::optimise_operations(operations.as_slice());
-						new_ops.push(Operation::Loop(loop_ops));
-					}
-				}
-				else {
-					let loop_ops = Operations::optimise_operations(operations.as_slice());
-					new_ops.push(Operation::Loop(loop_ops));
+			Operation::Loop(operations, span) => {
+				let loop_ops = Operations::optimise_operations(operations.as_slice(), overflow);
+				match (loop_ops.as_slice(), loop_ops.multiply_loop_ops(overflow)) {
+					(&[Operation::Move(stride, _)], _) if stride != 0 => new_ops.push(Operation::ScanZero(stride, *span)),
+					(_, Some(mul_add_ops)) => {
+						new_ops.extend(mul_add_ops);
+						new_ops.push(Operation::Set { offset: 0, value: C::ZERO, span: *span });
+					},
+					_ => new_ops.push(Operation::Loop(loop_ops, *span)),
 				}
 			},
-			Operation::SetValue(value) => {
+			Operation::Set { offset, value, span } => {
 				let last_mut = new_ops.last_mut();
 				match last_mut {
-					Some(Operation::Mod(_value)) => *last_mut.unwrap() = Operation::SetValue(*value),
-					Some(Operation::SetValue(_value)) => *last_mut.unwrap() = Operation::SetValue(*value),
-					_ => new_ops.push(Operation::SetValue(*value)),
+					// The modification could overflow, before being overwritten.
+					Some(Operation::Mod { offset: last_offset, span: last_span, .. })
+						if last_offset == offset && overflow != OverflowPolicy::Trapping =>
+					{
+						let span = last_span.union(*span);
+						*last_mut.unwrap() = Operation::Set { offset: *offset, value: *value, span }
+					},
+					Some(Operation::Set { offset: last_offset, span: last_span, .. }) if last_offset == offset => {
+						let span = last_span.union(*span);
+						*last_mut.unwrap() = Operation::Set { offset: *offset, value: *value, span }
+					},
+					_ => new_ops.push(Operation::Set { offset: *offset, value: *value, span: *span }),
 				}
 			},
-			Operation::GetInput => new_ops.push(Operation::GetInput),
-			Operation::PrintOutput => new_ops.push(Operation::PrintOutput),
+			Operation::GetInput(span) => new_ops.push(Operation::GetInput(*span)),
+			Operation::Print { offset, span } => new_ops.push(Operation::Print { offset: *offset, span: *span }),
+			Operation::MulAdd { offset, factor, span } => {
+				new_ops.push(Operation::MulAdd { offset: *offset, factor: *factor, span: *span })
+			},
+			Operation::ScanZero(stride, span) => new_ops.push(Operation::ScanZero(*stride, *span)),
+			Operation::DebugDump(span) => new_ops.push(Operation::DebugDump(*span)),
 		});
 		new_ops
 	}
+
+	/// Whether two modifications of the same value can be replaced by their sum.
+	fn can_merge(first: C::Signed, second: C::Signed, overflow: OverflowPolicy) -> bool {
+		let (first_value, second_value): (i32, i32) = (first.into(), second.into());
+		match overflow {
+			OverflowPolicy::Wrapping => true,
+			// The sum only saturates when one of the modifications would, if both have the same sign.
+			OverflowPolicy::Saturating => (first_value < 0) == (second_value < 0) && first.checked_add(second).is_some(),
+			// Every modification is kept, so an overflow is reported at the instruction that caused it.
+			OverflowPolicy::Trapping => false,
+		}
+	}
+
+	/// Replaces the moves in each basic block with offsets on the operations that support them,
+	/// so the pointer is only moved before operations that need it, and at the end of the block.
+	/// The move that is emitted covers the spans of all the moves it replaces.
+	/// With OverflowPolicy::Trapping, modifications are barriers like loops, and keep their offset.
+	fn sink_moves(old_ops: &[Operation<C>], overflow: OverflowPolicy) -> Operations<C> {
+		let mut new_ops = Operations::<C>::default();
+		let mut pending_move = 0;
+		let mut pending_span: Option<SourceSpan> = None;
+		for operation in old_ops {
+			let barrier = match operation {
+				Operation::Move(value, span) => {
+					pending_move += value;
+					pending_span = Some(pending_span.map_or(*span, |pending_span| pending_span.union(*span)));
+					None
+				},
+				// An overflow is reported with the memory pointer at the modified value.
+				Operation::Mod { offset, value, span } if overflow == OverflowPolicy::Trapping => {
+					Some(Operation::Mod { offset: *offset, value: *value, span: *span })
+				},
+				Operation::Mod { offset, value, span } => {
+					new_ops.push(Operation::Mod { offset: offset + pending_move, value: *value, span: *span });
+					None
+				},
+				Operation::Set { offset, value, span } => {
+					new_ops.push(Operation::Set { offset: offset + pending_move, value: *value, span: *span });
+					None
+				},
+				Operation::Print { offset, span } => {
+					new_ops.push(Operation::Print { offset: offset + pending_move, span: *span });
+					None
+				},
+				Operation::Loop(operations, span) => Some(Operation::Loop(Operations::sink_moves(operations.as_slice(), overflow), *span)),
+				Operation::GetInput(span) => Some(Operation::GetInput(*span)),
+				Operation::MulAdd { offset, factor, span } => Some(Operation::MulAdd { offset: *offset, factor: *factor, span: *span }),
+				Operation::ScanZero(stride, span) => Some(Operation::ScanZero(*stride, *span)),
+				Operation::DebugDump(span) => Some(Operation::DebugDump(*span)),
+			};
+			if let Some(barrier) = barrier {
+				if let Some(span) = pending_span.take() {
+					if pending_move != 0 {
+						new_ops.push(Operation::Move(pending_move, span));
+					}
+					pending_move = 0;
+				}
+				new_ops.push(barrier);
+			}
+		}
+		if let Some(span) = pending_span {
+			if pending_move != 0 {
+				new_ops.push(Operation::Move(pending_move, span));
+			}
+		}
+		new_ops
+	}
+
+	/// Converts the body of a loop into MulAdd operations, if the loop only modifies values,
+	/// returns to its starting position, and changes the value at that position by 1 or -1.
+	/// The loop must still be followed by setting the current value to 0.
+	/// Loops like "[-]" result in no MulAdd operations.
+	/// Without wrapping, the value at the starting position must count down,
+	/// and the modifications at each offset must be mergeable, so every MulAdd only overflows where the loop would.
+	/// With OverflowPolicy::Trapping only loops without MulAdd operations are converted, as the loop may stop
+	/// at an overflow after any iteration, and the memory must be left as the iterations before it left it.
+	fn multiply_loop_ops(&self, overflow: OverflowPolicy) -> Option<Vec<Operation<C>>> {
+		let mut position = 0i32;
+		// Sum of the modifications at each offset, in the order they first appear.
+		let mut modifications: Vec<(i32, C::Signed, SourceSpan)> = Vec::new();
+		for operation in self.iter() {
+			match operation {
+				Operation::Mod { offset, value, span } => {
+					let offset = position.checked_add(*offset)?;
+					match modifications.iter_mut().find(|(modified_offset, ..)| *modified_offset == offset) {
+						Some((_, sum, sum_span)) if Operations::<C>::can_merge(*sum, *value, overflow) => {
+							*sum = sum.wrapping_add(*value);
+							*sum_span = sum_span.union(*span);
+						},
+						Some(_) => return None,
+						None => modifications.push((offset, *value, *span)),
+					}
+				},
+				Operation::Move(value, _) => position = position.checked_add(*value)?,
+				_ => return None,
+			}
+		}
+		if position != 0 {
+			return None;
+		}
+		// When the loop counts up, the number of iterations is the negated starting value.
+		let factor_sign = match modifications.iter().find(|(offset, ..)| *offset == 0) {
+			Some((_, sum, _)) if *sum == C::Signed::ONE.wrapping_neg() => C::Signed::ONE,
+			Some((_, sum, _)) if *sum == C::Signed::ONE && overflow == OverflowPolicy::Wrapping => C::Signed::ONE.wrapping_neg(),
+			_ => return None,
+		};
+		let mul_add_ops = modifications
+			.into_iter()
+			.filter(|(offset, sum, _)| *offset != 0 && *sum != C::Signed::ZERO)
+			.map(|(offset, sum, span)| Operation::MulAdd { offset, factor: sum.wrapping_mul(factor_sign), span })
+			.collect::<Vec<_>>();
+		if overflow == OverflowPolicy::Trapping && !mul_add_ops.is_empty() {
+			return None;
+		}
+		Some(mul_add_ops)
+	}
 }
 
 
-impl Deref for Operations {
-	type Target = Vec<Operation>;
+impl<C: BfCell> Deref for Operations<C> {
+	type Target = Vec<Operation<C>>;
 
-	fn deref(&self) -> &Vec<Operation> {
+	fn deref(&self) -> &Vec<Operation<C>> {
 		&self.operations
 	}
 }
 
-impl DerefMut for Operations {
-	fn deref_mut(&mut self) -> &mut Vec<Operation> {
+impl<C: BfCell> DerefMut for Operations<C> {
+	fn deref_mut(&mut self) -> &mut Vec<Operation<C>> {
 		&mut self.operations
 	}
 }
+
+/// A range of bytes in the brainfuck source code, the end is exclusive.
+/// line and column are the position of the start, counted like in SourcePosition.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct SourceSpan {
+	pub start:  usize,
+	pub end:    usize,
+	pub line:   usize,
+	pub column: usize,
+}
+impl SourceSpan {
+	/// The smallest span covering both spans.
+	pub fn union(self, other: SourceSpan) -> SourceSpan {
+		let first = if other.start < self.start { other } else { self };
+		SourceSpan { end: self.end.max(other.end), ..first }
+	}
+
+	pub fn start_position(&self) -> SourcePosition {
+		SourcePosition { byte_offset: self.start, line: self.line, column: self.column }
+	}
+}
+// Kept short, as every operation in the verbose output has a span.
+impl std::fmt::Debug for SourceSpan {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}:{}@{}..{}", self.line, self.column, self.start, self.end)
+	}
+}
+
+/// Iterates over the characters of the source code, with the span of each character.
+/// Lines and columns are counted while iterating, instead of being calculated from the byte offsets.
+struct SourceCursor<'a> {
+	characters: std::str::CharIndices<'a>,
+	line:       usize,
+	column:     usize,
+}
+impl<'a> SourceCursor<'a> {
+	fn new(code: &'a str) -> SourceCursor<'a> {
+		SourceCursor { characters: code.char_indices(), line: 1, column: 1 }
+	}
+
+	/// Byte offset of the next character.
+	fn offset(&self) -> usize {
+		self.characters.offset()
+	}
+}
+impl Iterator for SourceCursor<'_> {
+	type Item = (char, SourceSpan);
+
+	fn next(&mut self) -> Option<(char, SourceSpan)> {
+		let (index, character) = self.characters.next()?;
+		let span = SourceSpan { start: index, end: index + character.len_utf8(), line: self.line, column: self.column };
+		if character == '\n' {
+			self.line += 1;
+			self.column = 1;
+		}
+		else {
+			self.column += 1;
+		}
+		Some((character, span))
+	}
+}
+
+/// A position in the brainfuck source code.
+/// Lines and columns are counted from 1, columns are counted in characters.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SourcePosition {
+	pub byte_offset: usize,
+	pub line:        usize,
+	pub column:      usize,
+}
+impl SourcePosition {
+	/// Calculates the line and column of the given byte offset in code.
+	pub fn from_byte_offset(code: &str, byte_offset: usize) -> SourcePosition {
+		let preceding = &code[..byte_offset];
+		let line_start = preceding.rfind('\n').map_or(0, |index| index + 1);
+		let line = preceding.matches('\n').count() + 1;
+		let column = preceding[line_start..].chars().count() + 1;
+		SourcePosition { byte_offset, line, column }
+	}
+
+	/// Finds the position of the character at line and column in code,
+	/// None if code has no such line, or the line has no such column.
+	pub fn from_line_column(code: &str, line: usize, column: usize) -> Option<SourcePosition> {
+		let line_start = match line {
+			0 => return None,
+			1 => 0,
+			_ => code.match_indices('\n').nth(line - 2)?.0 + 1,
+		};
+		let line_end = code[line_start..].find('\n').map_or(code.len(), |index| line_start + index);
+		let (index, _) = code[line_start..line_end].char_indices().nth(column.checked_sub(1)?)?;
+		Some(SourcePosition { byte_offset: line_start + index, line, column })
+	}
+}
+impl std::fmt::Display for SourcePosition {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "line {}, column {}", self.line, self.column)
+	}
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ParseErrorKind {
+	UnmatchedLoopEnd,
+	UnterminatedLoop,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseError {
+	kind:        ParseErrorKind,
+	position:    SourcePosition,
+	source_line: String,
+}
+impl ParseError {
+	fn new(kind: ParseErrorKind, code: &str, byte_offset: usize) -> ParseError {
+		let position = SourcePosition::from_byte_offset(code, byte_offset);
+		let line_start = code[..byte_offset].rfind('\n').map_or(0, |index| index + 1);
+		let line_end = code[byte_offset..].find('\n').map_or(code.len(), |index| byte_offset + index);
+		let source_line = code[line_start..line_end].trim_end_matches('\r').to_string();
+		ParseError { kind, position, source_line }
+	}
+
+	pub fn kind(&self) -> ParseErrorKind {
+		self.kind
+	}
+
+	/// Position of the offending bracket.
+	pub fn position(&self) -> SourcePosition {
+		self.position
+	}
+
+	/// Creates a multiline description of the error,
+	/// with the offending source line and a caret pointing at the bracket.
+	pub fn diagnostic(&self) -> String {
+		let line_number = self.position.line.to_string();
+		let gutter = " ".repeat(line_number.len());
+		// Keep tabs in the padding, so the caret lines up with the source line.
+		let padding: String = self
+			.source_line
+			.chars()
+			.take(self.position.column - 1)
+			.map(|character| if character == '\t' { '\t' } else { ' ' })
+			.collect();
+		format!(
+			"error: {}\n{}--> {}\n{} |\n{} | {}\n{} | {}^",
+			self.description(),
+			gutter,
+			self.position,
+			gutter,
+			line_number,
+			self.source_line,
+			gutter,
+			padding
+		)
+	}
+
+	fn description(&self) -> &'static str {
+		match self.kind {
+			ParseErrorKind::UnmatchedLoopEnd => "loop terminator ']' without matching '['",
+			ParseErrorKind::UnterminatedLoop => "loop started with '[' has no terminating ']'",
+		}
+	}
+}
+impl std::error::Error for ParseError {}
+impl std::fmt::Display for ParseError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} at {}", self.description(), self.position)
+	}
+}
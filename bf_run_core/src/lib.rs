@@ -21,4 +21,6 @@ pub fn read_bf_file_to_string(file_name: &str) -> std::io::Result<String> {
 	Ok(String::from_utf8_lossy(code_u8.as_ref()).into())
 }
 pub mod bf_memory;
+pub mod cell;
+pub mod compilers;
 pub mod executors;
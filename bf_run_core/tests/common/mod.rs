@@ -0,0 +1,71 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+// Each test uses a part of the helpers.
+#![allow(dead_code)]
+
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use bf_run_core::{bf_memory::BfMemory, executors::*};
+
+/// Output of a program, that can be read after the executor writing it has been consumed.
+#[derive(Debug, Clone, Default)]
+pub struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+impl SharedOutput {
+	/// Everything written so far.
+	pub fn contents(&self) -> Vec<u8> {
+		self.0.borrow().clone()
+	}
+}
+impl Write for SharedOutput {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.0.borrow_mut().write(buf)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+/// What running a program produced, compared between executors and settings.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Outcome {
+	/// The RunStatus, formatted with Debug.
+	pub status:  String,
+	pub output:  Vec<u8>,
+	pub pointer: i32,
+	/// Values of the memory at the indexes in tape_range.
+	pub tape:    Vec<u32>,
+}
+
+/// Runs code on executor E, with the given input and memory.
+pub fn run<M, E>(code: &str, input: &[u8], memory: M, options: ExecutorOptions, tape_range: std::ops::Range<i32>) -> Outcome
+where
+	M: BfMemory + std::fmt::Debug,
+	E: Executor<M, std::io::Cursor<Vec<u8>>, SharedOutput>, {
+	let output = SharedOutput::default();
+	let io = BfIo::new(std::io::Cursor::new(input.to_vec()), output.clone());
+	let result = E::new(code.to_string(), memory, io, options).expect("the program should parse").start();
+	let tape = tape_range.map(|index| result.memory.get_value(index).into()).collect();
+	let output = output.contents();
+	Outcome { status: format!("{:?}", result.status), output, pointer: result.pointer, tape }
+}
+
+/// Options with optimizations enabled or disabled, and the other settings at their defaults.
+pub fn options(enable_optimizations: bool) -> ExecutorOptions {
+	ExecutorOptions { enable_optimizations, ..ExecutorOptions::default() }
+}
@@ -0,0 +1,80 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use bf_run_core::{
+	bf_memory::{BfMemory, BfMemoryMemGuarded},
+	executors::{BfInterpreter, BfOptInterpreter, BfRecompiler},
+};
+use common::{options, run};
+
+/// Programs that move past the start or the end of the tape, through different kinds of operations.
+const PROGRAMS: [&str; 5] = ["+[<+]", "+[>+]", "+[>>>>+]", "+[[->+<]<+]", "+[>>>>[-]<<<+]"];
+
+fn out_of_bounds_status(code: &str) -> String {
+	let status = run::<_, BfInterpreter<_, _, _>>(code, b"", BfMemoryMemGuarded::<u8>::new(None), options(false), 0..0).status;
+	assert!(status.starts_with("Error(PointerOutOfBounds("), "{}: {}", code, status);
+	status
+}
+
+#[test]
+fn interpreters_report_pointer_out_of_bounds() {
+	for code in PROGRAMS {
+		let expected = out_of_bounds_status(code);
+		for enable_optimizations in [false, true] {
+			let memory = BfMemoryMemGuarded::<u8>::new(None);
+			let status = run::<_, BfOptInterpreter<_, _, _>>(code, b"", memory, options(enable_optimizations), 0..0).status;
+			assert_eq!(expected, status, "{}, optimizations {}", code, enable_optimizations);
+		}
+	}
+}
+
+#[test]
+#[cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), ignore)]
+fn recompiler_reports_pointer_out_of_bounds() {
+	for code in PROGRAMS {
+		let expected = out_of_bounds_status(code);
+		for enable_optimizations in [false, true] {
+			let memory = BfMemoryMemGuarded::<u8>::new(None);
+			let status = run::<_, BfRecompiler<_, _, _>>(code, b"", memory, options(enable_optimizations), 0..0).status;
+			assert_eq!(expected, status, "{}, optimizations {}", code, enable_optimizations);
+		}
+	}
+}
+
+/// Each thread attributes the faults of its recompiled code to its own memory.
+#[test]
+#[cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), ignore)]
+fn recompiler_reports_pointer_out_of_bounds_on_each_thread() {
+	let threads: Vec<_> = (0..8)
+		.map(|thread| {
+			std::thread::spawn(move || {
+				let code = PROGRAMS[thread % PROGRAMS.len()];
+				// Different sizes put index 0 at different distances from the guard regions.
+				let memory = BfMemoryMemGuarded::<u8>::new(Some(4096 << (thread % 3)));
+				let expected = run::<_, BfInterpreter<_, _, _>>(code, b"", memory, options(false), 0..0).status;
+				for _ in 0..100 {
+					let memory = BfMemoryMemGuarded::<u8>::new(Some(4096 << (thread % 3)));
+					let status = run::<_, BfRecompiler<_, _, _>>(code, b"", memory, options(true), 0..0).status;
+					assert_eq!(expected, status, "{}", code);
+				}
+			})
+		})
+		.collect();
+	threads.into_iter().for_each(|thread| thread.join().expect("the thread should not panic"));
+}
@@ -0,0 +1,114 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use bf_run_core::{
+	cell::BfCell,
+	executors::{
+		ir::{IrFormat, IrParseErrorKind},
+		operations::*,
+		OverflowPolicy,
+	},
+};
+
+const PROGRAMS: [&str; 5] = [
+	"",
+	"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+	"+++++[->--<]>[-]<<<,[.,]#",
+	"++[>+++[->++>-<<]<-]>>.>.",
+	"+[>>>>[-]<<<+]-[<]>>>+.",
+];
+
+/// Optimized IR, and what the optimizer makes of it.
+const OPTIMIZED: [(&str, &str); 6] = [
+	("loop {\n\tmod -1\n}\n", "set 0\n"),
+	("loop {\n\tmod -1\n\tmove +1\n\tmod +2\n\tmove -1\n}\n", "muladd +2 @1\nset 0\n"),
+	("mod +1\nmod +2\nmove +1\nmove -1\nprint\n", "mod +3\nprint\n"),
+	("loop {\n\tmove -1\n}\n", "scan -1\n"),
+	("move +2\nmod +1\nmove -1\nmod -3\nmove +1\nprint\n", "mod +1 @2\nmod -3 @1\nprint @2\nmove +2\n"),
+	("mod +5\nloop {\n\tmod -1\n}\nmod +3\nprint\n", "set 3\nprint\n"),
+];
+
+fn operations<C: BfCell>(code: &str, optimize: bool) -> Operations<C> {
+	let mut operations = Operations::conv_string_to_operations(code, true).unwrap();
+	if optimize {
+		operations.optimize(OverflowPolicy::Wrapping);
+	}
+	operations
+}
+
+fn assert_round_trip<C: BfCell>(operations: &Operations<C>) {
+	let ir = operations.to_ir(&IrFormat::default());
+	let parsed: Operations<C> = ir.parse().unwrap_or_else(|err| panic!("{} in\n{}", err, ir));
+	assert_eq!(parsed.to_ir(&IrFormat::default()), ir);
+
+	// The comments with the spans and statistics are ignored.
+	let commented = operations.to_ir(&IrFormat { spans: true, loop_stats: true });
+	let parsed: Operations<C> = commented.parse().unwrap_or_else(|err| panic!("{} in\n{}", err, commented));
+	assert_eq!(parsed.to_ir(&IrFormat::default()), ir);
+}
+
+#[test]
+fn ir_round_trips() {
+	for code in PROGRAMS {
+		for optimize in [false, true] {
+			assert_round_trip(&operations::<u8>(code, optimize));
+			assert_round_trip(&operations::<u16>(code, optimize));
+			assert_round_trip(&operations::<u32>(code, optimize));
+		}
+	}
+}
+
+#[test]
+fn optimize_ir() {
+	for (ir, expected) in OPTIMIZED {
+		let mut operations: Operations<u8> = ir.parse().unwrap();
+		operations.optimize(OverflowPolicy::Wrapping);
+		assert_eq!(operations.to_ir(&IrFormat::default()), expected, "optimizing\n{}", ir);
+	}
+}
+
+fn parse_error(ir: &str) -> (IrParseErrorKind, usize) {
+	let err = ir.parse::<Operations<u8>>().expect_err(ir);
+	(err.kind(), err.line())
+}
+
+#[test]
+fn unknown_operation() {
+	assert_eq!(parse_error("mod +1\njump +3\n"), (IrParseErrorKind::UnknownOperation, 2));
+	assert_eq!(parse_error("loop {\n\tMOD +1\n}\n"), (IrParseErrorKind::UnknownOperation, 2));
+}
+
+#[test]
+fn invalid_arguments() {
+	assert_eq!(parse_error("move\n"), (IrParseErrorKind::InvalidArguments, 1));
+	assert_eq!(parse_error("mod +1 2\n"), (IrParseErrorKind::InvalidArguments, 1));
+	assert_eq!(parse_error("print\nset 256\n"), (IrParseErrorKind::InvalidArguments, 2));
+	assert_eq!(parse_error("input +1\n"), (IrParseErrorKind::InvalidArguments, 1));
+	assert_eq!(parse_error("loop\n}\n"), (IrParseErrorKind::InvalidArguments, 1));
+}
+
+#[test]
+fn unmatched_loop_end() {
+	assert_eq!(parse_error("mod +1\n}\n"), (IrParseErrorKind::UnmatchedLoopEnd, 2));
+	assert_eq!(parse_error("loop {\n}\n}\n"), (IrParseErrorKind::UnmatchedLoopEnd, 3));
+}
+
+#[test]
+fn unterminated_loop() {
+	assert_eq!(parse_error("loop {\n\tloop {\n\t}\n"), (IrParseErrorKind::UnterminatedLoop, 1));
+	assert_eq!(parse_error("print\nloop {\n\tmod -1\n"), (IrParseErrorKind::UnterminatedLoop, 2));
+}
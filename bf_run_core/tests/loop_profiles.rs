@@ -0,0 +1,50 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use bf_run_core::{
+	bf_memory::{BfMemory, BfMemoryMemSafe},
+	executors::*,
+};
+use common::{options, SharedOutput};
+
+fn profile(code: &str, enable_optimizations: bool) -> Vec<LoopProfile> {
+	let io = BfIo::new(std::io::empty(), SharedOutput::default());
+	let interpreter = BfOptInterpreter::new(code.to_string(), BfMemoryMemSafe::<u8>::new(None), io, options(enable_optimizations))
+		.expect("the program should parse");
+	interpreter.start_profiled().1
+}
+
+/// Clear and multiply loops, and a loop that only modifies cells but is not replaced, as it decrements by 2.
+const CODE: &str = "++++[-]++++[->++<]++++[-->+<]";
+
+#[test]
+fn loops_that_only_modify_are_marked_when_optimized() {
+	let loops = profile(CODE, true);
+	assert_eq!(loops.len(), 1);
+	assert!(loops[0].only_modifies);
+	assert_eq!(loops[0].iterations, 2);
+}
+
+#[test]
+fn loops_are_not_marked_without_optimizations() {
+	let loops = profile(CODE, false);
+	assert_eq!(loops.len(), 3);
+	assert!(loops.iter().all(|loop_profile| !loop_profile.only_modifies));
+	assert_eq!(loops.iter().map(|loop_profile| loop_profile.iterations).collect::<Vec<_>>(), [4, 4, 2]);
+}
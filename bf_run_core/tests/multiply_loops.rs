@@ -0,0 +1,103 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use bf_run_core::{
+	bf_memory::{BfMemory, BfMemoryMemSafe},
+	cell::BfCell,
+	executors::{operations::*, *},
+};
+use common::{options, run, Outcome};
+
+/// Programs with loops that the optimizer replaces with MulAdd operations.
+const FOLDED: [&str; 5] = [
+	"++++[->++>+++<<]>.>.",
+	"+++++[->--<]>.",
+	"+++[>+++++<-]>[<++>-]<.",
+	"++[>+++[->++>-<<]<-]>>.>.",
+	"++++++[-<+>>+++<]<.>>.",
+];
+/// Programs with loops that must not be replaced, as they do not decrement the counter by one, or print.
+const NOT_FOLDED: [&str; 3] = ["++++++[-->+<]>.", "++++++++[>+<----]>.", "+++[>++<-.]"];
+/// Programs with multiply loops that overflow a cell partway through the loop.
+const OVERFLOWING: [&str; 3] = ["++[->-<]>.", "++++++++++++++++++[->+++++++++++++++<]>.", "+++[->>-<<]>>[->+<]>."];
+
+fn run_on<C: BfCell, E>(code: &str, enable_optimizations: bool, overflow: OverflowPolicy) -> Outcome
+where E: Executor<BfMemoryMemSafe<C>, std::io::Cursor<Vec<u8>>, common::SharedOutput> {
+	let options = ExecutorOptions { overflow, ..options(enable_optimizations) };
+	run::<_, E>(code, b"", BfMemoryMemSafe::<C>::new(None), options, -8..8)
+}
+
+fn assert_same_with_and_without_optimizations<C: BfCell>(code: &str, overflow: OverflowPolicy) {
+	let unoptimized = run_on::<C, BfOptInterpreter<_, _, _>>(code, false, overflow);
+	assert_eq!(unoptimized, run_on::<C, BfOptInterpreter<_, _, _>>(code, true, overflow), "new interpreter, {}", code);
+	if RECOMPILER_SUPPORTED {
+		assert_eq!(unoptimized, run_on::<C, BfRecompiler<_, _, _>>(code, false, overflow), "unoptimized recompiler, {}", code);
+		assert_eq!(unoptimized, run_on::<C, BfRecompiler<_, _, _>>(code, true, overflow), "recompiler, {}", code);
+	}
+}
+
+fn has_mul_add<C: BfCell>(operations: &[Operation<C>]) -> bool {
+	operations.iter().any(|operation| match operation {
+		Operation::MulAdd { .. } => true,
+		Operation::Loop(operations, _) => has_mul_add(operations),
+		_ => false,
+	})
+}
+
+fn optimized(code: &str, overflow: OverflowPolicy) -> Operations<u8> {
+	let mut operations = Operations::conv_string_to_operations(code, false).unwrap();
+	operations.optimize(overflow);
+	operations
+}
+
+#[test]
+fn multiply_loops_are_folded() {
+	for code in FOLDED {
+		assert!(has_mul_add(&optimized(code, OverflowPolicy::Wrapping)), "{} should contain MulAdd", code);
+	}
+	for code in NOT_FOLDED {
+		assert!(!has_mul_add(&optimized(code, OverflowPolicy::Wrapping)), "{} should not contain MulAdd", code);
+	}
+}
+
+#[test]
+fn multiply_loops_are_not_folded_when_trapping() {
+	for code in FOLDED.iter().chain(OVERFLOWING.iter()) {
+		assert!(!has_mul_add(&optimized(code, OverflowPolicy::Trapping)), "{} should not contain MulAdd", code);
+	}
+}
+
+#[test]
+fn multiply_loops_match_unoptimized() {
+	for code in FOLDED.iter().chain(NOT_FOLDED.iter()) {
+		assert_same_with_and_without_optimizations::<u8>(code, OverflowPolicy::Wrapping);
+		assert_same_with_and_without_optimizations::<u16>(code, OverflowPolicy::Wrapping);
+		assert_same_with_and_without_optimizations::<u32>(code, OverflowPolicy::Wrapping);
+	}
+}
+
+#[test]
+fn overflowing_multiply_loops_match_unoptimized() {
+	for code in FOLDED.iter().chain(OVERFLOWING.iter()) {
+		for overflow in [OverflowPolicy::Wrapping, OverflowPolicy::Saturating, OverflowPolicy::Trapping] {
+			assert_same_with_and_without_optimizations::<u8>(code, overflow);
+			assert_same_with_and_without_optimizations::<u16>(code, overflow);
+		}
+	}
+}
@@ -0,0 +1,64 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use bf_run_core::{
+	bf_memory::*,
+	cell::BfCell,
+	executors::{BfOptInterpreter, BfRecompiler},
+};
+use common::{options, run};
+
+/// Programs with their input, covering loops, input, output and every kind of optimized operation.
+const PROGRAMS: [(&str, &[u8]); 4] = [
+	(
+		"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.",
+		b"",
+	),
+	(",[.[-],]", b"echo"),
+	("+++++[>+++++<-]>[>++<-]>[<+>>+<-]<[[-]>]>>[-<<+>>]+[-<]>.", b""),
+	(">+>+>+>+<<<<-[>[-]<+]>>>>[<]<[.<]", b""),
+];
+
+fn assert_recompiler_matches_interpreter<M: BfMemory + std::fmt::Debug>() {
+	for (code, input) in PROGRAMS {
+		for enable_optimizations in [false, true] {
+			let interpreted = run::<_, BfOptInterpreter<_, _, _>>(code, input, M::new(None), options(enable_optimizations), 0..16);
+			let recompiled = run::<_, BfRecompiler<_, _, _>>(code, input, M::new(None), options(enable_optimizations), 0..16);
+			assert_eq!(interpreted, recompiled, "{}, optimizations {}", code, enable_optimizations);
+		}
+	}
+}
+
+fn assert_all_memories<C: BfCell + std::fmt::Debug>() {
+	assert_recompiler_matches_interpreter::<BfMemoryMemSafe<C>>();
+	assert_recompiler_matches_interpreter::<BfMemoryMemSafeSingleArray<C>>();
+	assert_recompiler_matches_interpreter::<BfMemoryMemUnsafe<C>>();
+	assert_recompiler_matches_interpreter::<BfMemoryMemGuarded<C>>();
+	assert_recompiler_matches_interpreter::<BfMemoryBounded<C>>();
+}
+
+/// Runs on every architecture with a backend, for AArch64 with
+/// "cargo test --target aarch64-unknown-linux-gnu" under qemu-user, see .cargo/config.toml.
+#[test]
+#[cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), ignore)]
+fn recompiler_matches_interpreter() {
+	assert_all_memories::<u8>();
+	assert_all_memories::<u16>();
+	assert_all_memories::<u32>();
+}
@@ -0,0 +1,123 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use std::{
+	io::Write,
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+};
+
+use bf_run_core::{
+	bf_memory::{BfMemory, BfMemoryMemSafe},
+	cell::BfCell,
+	compilers::{CompilerOptions, RustTranspiler},
+	executors::*,
+};
+use common::SharedOutput;
+
+const PROGRAMS: [&str; 4] = [
+	"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.",
+	"++++[>++++[>++++<-]<-]>>+.",
+	"-.>+++++[<+++++>-]<.",
+	"++++++[->++>+++<<]>.>.<<+++[->>[->+<]<]>>>.",
+];
+/// Reads past the end of its input, so the output depends on the EOF policy.
+const READS_PAST_INPUT: &str = ",.,.,.,.";
+const INPUT: &[u8] = b"ab";
+const EOF_POLICIES: [EofPolicy; 4] = [EofPolicy::Unchanged, EofPolicy::Zero, EofPolicy::MinusOne, EofPolicy::Error];
+
+/// A directory for the transpiled programs of one test, removed when dropped.
+struct TempDir(PathBuf);
+impl TempDir {
+	fn new(name: &str) -> TempDir {
+		let path = std::env::temp_dir().join(format!("bf_run_{}_{}", name, std::process::id()));
+		std::fs::create_dir_all(&path).unwrap();
+		TempDir(path)
+	}
+}
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}
+
+/// Output of the program, and whether it finished without an error.
+fn interpret<C: BfCell>(code: &str, input: &[u8], eof_policy: EofPolicy) -> (Vec<u8>, bool) {
+	let output = SharedOutput::default();
+	let io = BfIo::new(std::io::Cursor::new(input.to_vec()), output.clone()).with_eof_policy(eof_policy);
+	let interpreter = BfOptInterpreter::new(code.to_string(), BfMemoryMemSafe::<C>::new(None), io, ExecutorOptions::default());
+	let result = interpreter.expect("the program should parse").start();
+	(output.contents(), matches!(result.status, RunStatus::Finished))
+}
+
+/// Builds the transpiled program with rustc in dir, and runs it with input.
+fn compile_and_run<C: BfCell>(dir: &Path, code: &str, input: &[u8], options: CompilerOptions) -> (Vec<u8>, bool) {
+	let name = format!("program_{}", dir.read_dir().unwrap().count());
+	let source_path = dir.join(format!("{}.rs", name));
+	let executable_path = dir.join(&name);
+	let transpiler = RustTranspiler::<C>::new(code, options).expect("the program should parse");
+	std::fs::write(&source_path, transpiler.transpile()).unwrap();
+
+	let rustc = Command::new(std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+		.args(["--edition", "2021", "-O", "-o"])
+		.arg(&executable_path)
+		.arg(&source_path)
+		.output()
+		.expect("rustc should run");
+	assert!(rustc.status.success(), "rustc failed for {}:\n{}", code, String::from_utf8_lossy(&rustc.stderr));
+
+	let mut child = Command::new(&executable_path)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.spawn()
+		.unwrap();
+	child.stdin.take().unwrap().write_all(input).unwrap();
+	let result = child.wait_with_output().unwrap();
+	(result.stdout, result.status.success())
+}
+
+fn assert_same_as_interpreter<C: BfCell>(dir: &Path, code: &str, input: &[u8], options: CompilerOptions) {
+	let expected = interpret::<C>(code, input, options.eof_policy);
+	let description = format!("{}, {} bit cells, {:?}", code, C::SIZE * 8, options);
+	assert_eq!(expected, compile_and_run::<C>(dir, code, input, options), "{}", description);
+}
+
+#[test]
+fn transpiled_programs_match_the_interpreter() {
+	let dir = TempDir::new("rust_transpiler_programs");
+	for code in PROGRAMS {
+		for enable_optimizations in [false, true] {
+			let options = CompilerOptions { enable_optimizations, ..CompilerOptions::default() };
+			assert_same_as_interpreter::<u8>(&dir.0, code, b"", options.clone());
+			assert_same_as_interpreter::<u16>(&dir.0, code, b"", options.clone());
+			assert_same_as_interpreter::<u32>(&dir.0, code, b"", options);
+		}
+	}
+}
+
+#[test]
+fn transpiled_programs_apply_the_eof_policy() {
+	let dir = TempDir::new("rust_transpiler_eof");
+	for eof_policy in EOF_POLICIES {
+		let options = CompilerOptions { eof_policy, ..CompilerOptions::default() };
+		assert_same_as_interpreter::<u8>(&dir.0, READS_PAST_INPUT, INPUT, options.clone());
+		assert_same_as_interpreter::<u16>(&dir.0, READS_PAST_INPUT, INPUT, options);
+	}
+}
@@ -0,0 +1,93 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use bf_run_core::{
+	bf_memory::{BfMemory, BfMemoryBounded, TapeEdgePolicy},
+	executors::{BfInterpreter, BfOptInterpreter, BfRecompiler, RECOMPILER_SUPPORTED},
+};
+use common::{options, run, Outcome};
+
+const TAPE_LEN: usize = 8;
+const POLICIES: [TapeEdgePolicy; 3] = [TapeEdgePolicy::Error, TapeEdgePolicy::Wrap, TapeEdgePolicy::Clamp];
+/// Programs that move the memory pointer off either end of the tape, and some that come back before accessing a cell,
+/// with the length of their tape.
+const PROGRAMS: [(&str, usize); 9] = [
+	("<>+", TAPE_LEN),
+	("<<<+>>>>+", TAPE_LEN),
+	("+[<+]", TAPE_LEN),
+	("+[>+]", TAPE_LEN),
+	(">>>>>>>>+.", TAPE_LEN),
+	("++++[>++++++++<-]>[>+>+<<-]>>>>>>>+<<<<<<<<<<+", TAPE_LEN),
+	("+++[>>>>>+++<<<-]>>>>>>[-]<<<<<<<<<<-.", TAPE_LEN),
+	// Moves off the tape with a modified current cell, which clamping and wrapping keep the pointer at.
+	("+++<.", TAPE_LEN),
+	("+++>.", 1),
+];
+
+fn run_on(code: &str, tape_len: usize, edge_policy: TapeEdgePolicy) -> Vec<(&'static str, Outcome)> {
+	let memory = || BfMemoryBounded::<u8>::new(Some(tape_len)).with_edge_policy(edge_policy);
+	let tape = 0..tape_len as i32;
+	let mut outcomes = vec![
+		("old interpreter", run::<_, BfInterpreter<_, _, _>>(code, b"", memory(), options(false), tape.clone())),
+		("new interpreter", run::<_, BfOptInterpreter<_, _, _>>(code, b"", memory(), options(true), tape.clone())),
+		("unoptimized new interpreter", run::<_, BfOptInterpreter<_, _, _>>(code, b"", memory(), options(false), tape.clone())),
+	];
+	if RECOMPILER_SUPPORTED {
+		outcomes.push(("recompiler", run::<_, BfRecompiler<_, _, _>>(code, b"", memory(), options(true), tape.clone())));
+		outcomes.push(("unoptimized recompiler", run::<_, BfRecompiler<_, _, _>>(code, b"", memory(), options(false), tape)));
+	}
+	outcomes
+}
+
+#[test]
+fn executors_apply_the_same_edge_policy() {
+	for edge_policy in POLICIES {
+		for (code, tape_len) in PROGRAMS {
+			let outcomes = run_on(code, tape_len, edge_policy);
+			let (_, expected) = &outcomes[0];
+			for (executor, outcome) in &outcomes[1..] {
+				assert_eq!(expected, outcome, "{}, {:?}, {}", executor, edge_policy, code);
+			}
+		}
+	}
+}
+
+#[test]
+fn moving_off_the_tape_applies_the_edge_policy() {
+	let outcome = |code, edge_policy| run_on(code, TAPE_LEN, edge_policy).swap_remove(0).1;
+
+	// The pointer stays at the last cell it was at, when it can not move.
+	let stopped = outcome("<>+", TapeEdgePolicy::Error);
+	assert_eq!(stopped.status, "Error(PointerOutOfBounds(-1))");
+	assert_eq!(stopped.pointer, 0);
+	let stopped = outcome("+>>>>>>>>+", TapeEdgePolicy::Error);
+	assert_eq!(stopped.status, "Error(PointerOutOfBounds(8))");
+	assert_eq!((stopped.pointer, stopped.tape[0]), (7, 1));
+
+	let wrapped = outcome("<+<++>>>+++", TapeEdgePolicy::Wrap);
+	assert_eq!(wrapped.status, "Finished");
+	assert_eq!((wrapped.pointer, wrapped.tape[7], wrapped.tape[6], wrapped.tape[0], wrapped.tape[1]), (1, 1, 2, 0, 3));
+
+	// Every move off the start leaves the pointer at the first cell, which is incremented until it wraps to 0.
+	let clamped = outcome("+[<+]", TapeEdgePolicy::Clamp);
+	assert_eq!(clamped.status, "Finished");
+	assert_eq!((clamped.pointer, clamped.tape[0], clamped.tape[1]), (0, 0, 0));
+	let clamped = outcome(">>>>>>>>>>+<+", TapeEdgePolicy::Clamp);
+	assert_eq!((clamped.pointer, clamped.tape[7], clamped.tape[6]), (6, 1, 1));
+}
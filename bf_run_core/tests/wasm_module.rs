@@ -0,0 +1,188 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use bf_run_core::{
+	cell::BfCell,
+	compilers::{CompilerOptions, WasmCompiler},
+};
+
+const PROGRAMS: [&str; 4] = ["", "+.", ",[.,]", "++++[>++++[>++<-]<-]>>[-<+>]<<[>>+<<-]>[[-]<]>."];
+
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const FUNCTION_SECTION: u8 = 3;
+const MEMORY_SECTION: u8 = 5;
+const EXPORT_SECTION: u8 = 7;
+const CODE_SECTION: u8 = 10;
+
+/// Reads the parts of a binary module that the compiler creates.
+struct Reader<'a> {
+	bytes:    &'a [u8],
+	position: usize,
+}
+impl<'a> Reader<'a> {
+	fn new(bytes: &'a [u8]) -> Reader<'a> {
+		Reader { bytes, position: 0 }
+	}
+
+	fn at_end(&self) -> bool {
+		self.position == self.bytes.len()
+	}
+
+	fn byte(&mut self) -> u8 {
+		let byte = self.bytes[self.position];
+		self.position += 1;
+		byte
+	}
+
+	fn bytes(&mut self, length: usize) -> &'a [u8] {
+		let bytes = &self.bytes[self.position..self.position + length];
+		self.position += length;
+		bytes
+	}
+
+	/// Reads a value in the unsigned LEB128 encoding.
+	fn unsigned(&mut self) -> u32 {
+		let mut value = 0;
+		let mut shift = 0;
+		loop {
+			let byte = self.byte();
+			value |= ((byte & 0x7f) as u32) << shift;
+			if byte & 0x80 == 0 {
+				return value;
+			}
+			shift += 7;
+		}
+	}
+
+	fn name(&mut self) -> String {
+		let length = self.unsigned() as usize;
+		String::from_utf8(self.bytes(length).to_vec()).expect("names should be UTF-8")
+	}
+}
+
+/// The sections of module after the header, as their id and contents.
+fn sections(module: &[u8]) -> Vec<(u8, &[u8])> {
+	let mut reader = Reader::new(&module[8..]);
+	let mut sections = Vec::new();
+	while !reader.at_end() {
+		let id = reader.byte();
+		let length = reader.unsigned() as usize;
+		sections.push((id, reader.bytes(length)));
+	}
+	sections
+}
+
+fn section(module: &[u8], id: u8) -> Reader<'_> {
+	let (_, contents) = sections(module).into_iter().find(|(section_id, _)| *section_id == id).expect("the section should exist");
+	Reader::new(contents)
+}
+
+fn compile<C: BfCell>(code: &str, memory_size: Option<usize>) -> Vec<u8> {
+	let options = CompilerOptions { memory_size, ..CompilerOptions::default() };
+	WasmCompiler::<C>::new(code, options).expect("the program should parse").compile()
+}
+
+/// Module of every program, with every cell size.
+fn modules() -> Vec<Vec<u8>> {
+	PROGRAMS.iter().flat_map(|code| [compile::<u8>(code, None), compile::<u16>(code, None), compile::<u32>(code, None)]).collect()
+}
+
+#[test]
+fn module_starts_with_magic_and_version() {
+	for module in modules() {
+		assert_eq!(&module[..4], b"\0asm");
+		assert_eq!(&module[4..8], &[1, 0, 0, 0]);
+	}
+}
+
+#[test]
+fn sections_are_in_order() {
+	for module in modules() {
+		let ids: Vec<u8> = sections(&module).iter().map(|(id, _)| *id).collect();
+		assert_eq!(ids, [TYPE_SECTION, IMPORT_SECTION, FUNCTION_SECTION, MEMORY_SECTION, EXPORT_SECTION, CODE_SECTION]);
+	}
+}
+
+#[test]
+fn imports_read_byte_and_write_byte() {
+	for module in modules() {
+		let mut types = section(&module, TYPE_SECTION);
+		assert_eq!(types.unsigned(), 3);
+		// read_byte: [] -> [i32], write_byte: [i32] -> [], run: [] -> [].
+		assert_eq!(types.bytes(11), &[0x60, 0x00, 0x01, 0x7f, 0x60, 0x01, 0x7f, 0x00, 0x60, 0x00, 0x00]);
+		assert!(types.at_end());
+
+		let mut imports = section(&module, IMPORT_SECTION);
+		assert_eq!(imports.unsigned(), 2);
+		for (name, type_index) in [("read_byte", 0), ("write_byte", 1)] {
+			assert_eq!(imports.name(), "env");
+			assert_eq!(imports.name(), name);
+			assert_eq!(imports.byte(), 0x00, "{} should be a function", name);
+			assert_eq!(imports.unsigned(), type_index);
+		}
+		assert!(imports.at_end());
+	}
+}
+
+#[test]
+fn exports_run_and_memory() {
+	for module in modules() {
+		let mut functions = section(&module, FUNCTION_SECTION);
+		assert_eq!(functions.unsigned(), 1);
+		assert_eq!(functions.unsigned(), 2, "run should have the third type");
+		assert!(functions.at_end());
+
+		let mut exports = section(&module, EXPORT_SECTION);
+		assert_eq!(exports.unsigned(), 2);
+		assert_eq!(exports.name(), "run");
+		assert_eq!(exports.byte(), 0x00);
+		assert_eq!(exports.unsigned(), 2, "run should come after the two imported functions");
+		assert_eq!(exports.name(), "memory");
+		assert_eq!(exports.byte(), 0x02);
+		assert_eq!(exports.unsigned(), 0);
+		assert!(exports.at_end());
+
+		let mut code = section(&module, CODE_SECTION);
+		assert_eq!(code.unsigned(), 1);
+		let length = code.unsigned() as usize;
+		let body = code.bytes(length);
+		assert!(code.at_end());
+		assert_eq!(body.last(), Some(&0x0b), "the body should end with end");
+	}
+}
+
+#[test]
+fn memory_has_enough_pages_for_the_cells() {
+	fn memory_pages(module: &[u8]) -> u32 {
+		let mut memories = section(module, MEMORY_SECTION);
+		assert_eq!(memories.unsigned(), 1);
+		assert_eq!(memories.byte(), 0x00, "the memory should have no maximum");
+		let pages = memories.unsigned();
+		assert!(memories.at_end());
+		pages
+	}
+
+	assert_eq!(memory_pages(&compile::<u8>("+", None)), 1);
+	assert_eq!(memory_pages(&compile::<u16>("+", None)), 2);
+	assert_eq!(memory_pages(&compile::<u32>("+", None)), 4);
+	assert_eq!(memory_pages(&compile::<u8>("+", Some(1))), 1);
+	assert_eq!(memory_pages(&compile::<u8>("+", Some(65536))), 1);
+	assert_eq!(memory_pages(&compile::<u8>("+", Some(65537))), 2);
+	assert_eq!(memory_pages(&compile::<u16>("+", Some(200_000))), 7);
+	assert_eq!(memory_pages(&compile::<u32>("+", Some(1 << 20))), 64);
+}
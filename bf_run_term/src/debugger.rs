@@ -0,0 +1,259 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+	collections::BTreeSet,
+	io::{BufRead, Read, Write},
+};
+
+use bf_run_core::{
+	bf_memory::BfMemory,
+	executors::{operations::SourcePosition, BfInterpreter},
+};
+
+/// Number of cells shown on each side of the memory pointer, when the tape command has no argument.
+const DEFAULT_TAPE_RADIUS: i32 = 8;
+
+const HELP: &str = "\
+Commands:
+  s, step [count]       Execute the next instruction, or the next count instructions.
+  n, next               Execute the next instruction, running a loop to its end if the instruction starts one.
+  c, continue           Run until a breakpoint is reached, or the program finishes.
+  b, break LINE:COLUMN  Set a breakpoint at the first instruction from the position.
+  d, delete LINE:COLUMN Delete the breakpoint of the position.
+  i, info               List the breakpoints.
+  t, tape [radius]      Print the cells around the memory pointer.
+  w, where              Print the position of the next instruction.
+  h, help               Print this help.
+  q, quit               Stop debugging.
+An empty line repeats the last command.
+Every '#' in the source sets a breakpoint at the instruction after it.";
+
+/// Steps through a program on a BfInterpreter, controlled by commands read from stdin.
+pub(crate) struct Debugger<T, R, W> {
+	interpreter: BfInterpreter<T, R, W>,
+	/// Byte offsets of the instructions to stop at.
+	breakpoints: BTreeSet<usize>,
+	/// Set when the program has finished, or stopped with an error.
+	finished:    bool,
+}
+impl<T: BfMemory + std::fmt::Debug, R: Read, W: Write> Debugger<T, R, W> {
+	pub(crate) fn new(interpreter: BfInterpreter<T, R, W>) -> Debugger<T, R, W> {
+		let code = interpreter.code();
+		let breakpoints = code
+			.match_indices('#')
+			.filter_map(|(index, _)| first_instruction(code, index))
+			.collect();
+		Debugger { interpreter, breakpoints, finished: false }
+	}
+
+	pub(crate) fn run(mut self) {
+		println!("Debugging {} characters of brainfuck, type 'help' for a list of commands.", self.interpreter.code().len());
+		self.print_location();
+		let mut last_command = String::new();
+		let stdin = std::io::stdin();
+		loop {
+			print!("(bf_run) ");
+			std::io::stdout().flush().ok();
+			let mut line = String::new();
+			match stdin.lock().read_line(&mut line) {
+				Ok(0) | Err(_) => return,
+				Ok(_) => (),
+			}
+			let line = match line.trim() {
+				"" => std::mem::take(&mut last_command),
+				line => line.to_string(),
+			};
+			let mut words = line.split_whitespace();
+			let argument = words.clone().nth(1);
+			match words.next().unwrap_or_default() {
+				"s" | "step" => match argument.map_or(Ok(1), str::parse::<u64>) {
+					Ok(count) => self.step(count),
+					Err(_) => println!("Invalid step count '{}'", argument.unwrap_or_default()),
+				},
+				"n" | "next" => self.step_over(),
+				"c" | "continue" => self.resume(),
+				"b" | "break" => self.set_breakpoint(argument, true),
+				"d" | "delete" => self.set_breakpoint(argument, false),
+				"i" | "info" => self.print_breakpoints(),
+				"t" | "tape" => match argument.map_or(Ok(DEFAULT_TAPE_RADIUS), str::parse::<i32>) {
+					Ok(radius) if radius >= 0 => self.print_tape(radius),
+					_ => println!("Invalid tape radius '{}'", argument.unwrap_or_default()),
+				},
+				"w" | "where" => self.print_location(),
+				"h" | "help" => println!("{}", HELP),
+				"q" | "quit" => return,
+				"" => (),
+				command => println!("Unknown command '{}', type 'help' for a list of commands.", command),
+			}
+			last_command = line;
+		}
+	}
+
+	/// Executes one instruction, returns false if the program can not continue.
+	fn execute(&mut self) -> bool {
+		if self.finished {
+			return false;
+		}
+		match self.interpreter.step() {
+			Ok(true) => true,
+			Ok(false) => {
+				self.finish(None);
+				false
+			},
+			Err(err) => {
+				self.finish(Some(err));
+				false
+			},
+		}
+	}
+
+	fn finish(&mut self, error: Option<bf_run_core::executors::RuntimeError>) {
+		self.finished = true;
+		self.interpreter.flush_output().ok();
+		match error {
+			Some(err) => println!("\nError: {}", err),
+			None => println!("\nThe program finished after {} steps.", self.interpreter.steps()),
+		}
+	}
+
+	/// Whether execution is at a breakpoint.
+	fn at_breakpoint(&self) -> bool {
+		self.interpreter
+			.next_instruction()
+			.is_some_and(|position| self.breakpoints.contains(&position))
+	}
+
+	fn step(&mut self, count: u64) {
+		for _ in 0..count {
+			if !self.execute() {
+				return;
+			}
+		}
+		self.stop();
+	}
+
+	fn step_over(&mut self) {
+		let depth = self.interpreter.loop_depth();
+		if !self.execute() {
+			return;
+		}
+		// The loop ends when the loop depth is back where it started.
+		while self.interpreter.loop_depth() > depth && !self.at_breakpoint() {
+			if !self.execute() {
+				return;
+			}
+		}
+		self.stop();
+	}
+
+	fn resume(&mut self) {
+		if !self.execute() {
+			return;
+		}
+		while !self.at_breakpoint() {
+			if !self.execute() {
+				return;
+			}
+		}
+		self.stop();
+	}
+
+	/// Shows where execution stopped, with the cells around the memory pointer.
+	fn stop(&mut self) {
+		self.interpreter.flush_output().ok();
+		self.print_location();
+		self.print_tape(DEFAULT_TAPE_RADIUS);
+	}
+
+	fn set_breakpoint(&mut self, argument: Option<&str>, enable: bool) {
+		let code = self.interpreter.code();
+		let position = argument
+			.and_then(|argument| argument.split_once(':'))
+			.and_then(|(line, column)| Some((line.parse().ok()?, column.parse().ok()?)))
+			.and_then(|(line, column)| SourcePosition::from_line_column(code, line, column));
+		let Some(position) = position
+		else {
+			println!("Expected a position like 'LINE:COLUMN', with a line and column of the source.");
+			return;
+		};
+		let Some(instruction) = first_instruction(code, position.byte_offset)
+		else {
+			println!("There are no instructions from {}.", position);
+			return;
+		};
+		let instruction_position = SourcePosition::from_byte_offset(code, instruction);
+		match enable {
+			true if self.breakpoints.insert(instruction) => println!("Breakpoint set at {}.", instruction_position),
+			true => println!("There already is a breakpoint at {}.", instruction_position),
+			false if self.breakpoints.remove(&instruction) => println!("Deleted the breakpoint at {}.", instruction_position),
+			false => println!("There is no breakpoint at {}.", instruction_position),
+		}
+	}
+
+	fn print_breakpoints(&self) {
+		if self.breakpoints.is_empty() {
+			println!("There are no breakpoints.");
+		}
+		for breakpoint in &self.breakpoints {
+			println!("Breakpoint at {}", SourcePosition::from_byte_offset(self.interpreter.code(), *breakpoint));
+		}
+	}
+
+	/// Prints the line of the next instruction, with a marker below the instruction.
+	fn print_location(&self) {
+		let code = self.interpreter.code();
+		let Some(next_instruction) = self.interpreter.next_instruction()
+		else {
+			println!("The program has finished.");
+			return;
+		};
+		let position = SourcePosition::from_byte_offset(code, next_instruction);
+		let line_start = code[..next_instruction].rfind('\n').map_or(0, |index| index + 1);
+		let line_end = code[next_instruction..].find('\n').map_or(code.len(), |index| next_instruction + index);
+		println!("Step {}, next instruction at {}:", self.interpreter.steps(), position);
+		println!("{}", &code[line_start..line_end]);
+		println!("{}^", " ".repeat(position.column - 1));
+	}
+
+	/// Prints the index and value of the cells within radius of the memory pointer.
+	/// The current cell is enclosed in brackets.
+	fn print_tape(&self, radius: i32) {
+		let pointer = self.interpreter.pointer();
+		let memory = self.interpreter.memory();
+		let (mut indexes, mut values) = (String::new(), String::new());
+		for index in pointer.saturating_sub(radius)..=pointer.saturating_add(radius) {
+			let value: u32 = memory.get_value(index).into();
+			let value = match index == pointer {
+				true => format!("[{}]", value),
+				false => value.to_string(),
+			};
+			let width = value.len().max(index.to_string().len()) + 1;
+			indexes.push_str(&format!("{:>width$}", index));
+			values.push_str(&format!("{:>width$}", value));
+		}
+		println!("Index:{}\nValue:{}", indexes, values);
+	}
+}
+
+/// Byte offset of the first instruction in code, from the byte offset start.
+fn first_instruction(code: &str, start: usize) -> Option<usize> {
+	code.as_bytes()[start..]
+		.iter()
+		.position(|character| matches!(character, b'+' | b'-' | b'<' | b'>' | b',' | b'.' | b'[' | b']'))
+		.map(|distance| start + distance)
+}
@@ -14,10 +14,14 @@
 	You should have received a copy of the GNU General Public License
 	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
 */
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use static_dispath::static_dispatch;
 
-#[derive(Debug)]
+mod debugger;
+mod profiler;
+
+#[derive(Debug, Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
 enum ExecutorArg {
 	OldInterpreterArg,
 	NewInterpreterArg,
@@ -36,11 +40,14 @@ impl std::str::FromStr for ExecutorArg {
 	}
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone, Copy)]
+#[allow(clippy::enum_variant_names)]
 enum MemoryType {
 	UnsafeArrayArg,
 	DualArrayArg,
 	SingleArrayArg,
+	GuardedArrayArg,
+	BoundedArrayArg,
 }
 impl std::str::FromStr for MemoryType {
 	type Err = ArgumentParseError;
@@ -50,15 +57,105 @@ impl std::str::FromStr for MemoryType {
 			"ua" => Ok(MemoryType::UnsafeArrayArg),
 			"da" => Ok(MemoryType::DualArrayArg),
 			"sa" => Ok(MemoryType::SingleArrayArg),
+			"ga" => Ok(MemoryType::GuardedArrayArg),
+			"ba" => Ok(MemoryType::BoundedArrayArg),
 			_ => Err(ArgumentParseError::MemoryTypeParseError(s.to_string())),
 		}
 	}
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+enum CellSize {
+	Cell8Arg,
+	Cell16Arg,
+	Cell32Arg,
+}
+impl std::str::FromStr for CellSize {
+	type Err = ArgumentParseError;
+
+	fn from_str(s: &str) -> Result<CellSize, ArgumentParseError> {
+		match s {
+			"8" => Ok(CellSize::Cell8Arg),
+			"16" => Ok(CellSize::Cell16Arg),
+			"32" => Ok(CellSize::Cell32Arg),
+			_ => Err(ArgumentParseError::CellSizeParseError(s.to_string())),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TranspileTarget {
+	C,
+	Rust,
+	RustFunction,
+	Wasm,
+	Llvm,
+}
+impl std::str::FromStr for TranspileTarget {
+	type Err = ArgumentParseError;
+
+	fn from_str(s: &str) -> Result<TranspileTarget, ArgumentParseError> {
+		match s {
+			"c" => Ok(TranspileTarget::C),
+			"rust" => Ok(TranspileTarget::Rust),
+			"rust-fn" => Ok(TranspileTarget::RustFunction),
+			"wasm" => Ok(TranspileTarget::Wasm),
+			"llvm" => Ok(TranspileTarget::Llvm),
+			_ => Err(ArgumentParseError::TranspileTargetParseError(s.to_string())),
+		}
+	}
+}
+
+fn parse_eof_policy(s: &str) -> Result<bf_run_core::executors::EofPolicy, ArgumentParseError> {
+	use bf_run_core::executors::EofPolicy;
+	match s {
+		"unchanged" => Ok(EofPolicy::Unchanged),
+		"0" => Ok(EofPolicy::Zero),
+		"255" => Ok(EofPolicy::MinusOne),
+		"error" => Ok(EofPolicy::Error),
+		_ => Err(ArgumentParseError::EofPolicyParseError(s.to_string())),
+	}
+}
+
+fn parse_overflow_policy(s: &str) -> Result<bf_run_core::executors::OverflowPolicy, ArgumentParseError> {
+	use bf_run_core::executors::OverflowPolicy;
+	match s {
+		"wrap" => Ok(OverflowPolicy::Wrapping),
+		"saturate" => Ok(OverflowPolicy::Saturating),
+		"trap" => Ok(OverflowPolicy::Trapping),
+		_ => Err(ArgumentParseError::OverflowPolicyParseError(s.to_string())),
+	}
+}
+
+fn parse_tape_edge_policy(s: &str) -> Result<bf_run_core::bf_memory::TapeEdgePolicy, ArgumentParseError> {
+	use bf_run_core::bf_memory::TapeEdgePolicy;
+	match s {
+		"error" => Ok(TapeEdgePolicy::Error),
+		"wrap" => Ok(TapeEdgePolicy::Wrap),
+		"clamp" => Ok(TapeEdgePolicy::Clamp),
+		_ => Err(ArgumentParseError::TapeEdgePolicyParseError(s.to_string())),
+	}
+}
+
+fn parse_timeout(s: &str) -> Result<std::time::Duration, ArgumentParseError> {
+	s.parse::<f64>()
+		.ok()
+		.and_then(|seconds| std::time::Duration::try_from_secs_f64(seconds).ok())
+		.ok_or_else(|| ArgumentParseError::TimeoutParseError(s.to_string()))
+}
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 enum ArgumentParseError {
 	ExecutorParseError(String),
 	MemoryTypeParseError(String),
+	CellSizeParseError(String),
+	EofPolicyParseError(String),
+	OverflowPolicyParseError(String),
+	TapeEdgePolicyParseError(String),
+	TimeoutParseError(String),
+	TranspileTargetParseError(String),
 }
 impl std::error::Error for ArgumentParseError {}
 impl std::fmt::Display for ArgumentParseError {
@@ -66,57 +163,438 @@ impl std::fmt::Display for ArgumentParseError {
 		match self {
 			ArgumentParseError::ExecutorParseError(err_string) => write!(f, "Error parsing executor string '{}'", err_string),
 			ArgumentParseError::MemoryTypeParseError(err_string) => write!(f, "Error parsing memory type '{}'", err_string),
+			ArgumentParseError::CellSizeParseError(err_string) => write!(f, "Error parsing cell size '{}'", err_string),
+			ArgumentParseError::EofPolicyParseError(err_string) => write!(f, "Error parsing eof behaviour '{}'", err_string),
+			ArgumentParseError::OverflowPolicyParseError(err_string) => write!(f, "Error parsing overflow behaviour '{}'", err_string),
+			ArgumentParseError::TapeEdgePolicyParseError(err_string) => write!(f, "Error parsing tape edge behaviour '{}'", err_string),
+			ArgumentParseError::TimeoutParseError(err_string) => write!(f, "Error parsing timeout '{}'", err_string),
+			ArgumentParseError::TranspileTargetParseError(err_string) => write!(f, "Error parsing transpile target '{}'", err_string),
 		}
 	}
 }
 
 #[derive(Parser, Debug)]
 #[clap(name = "Brainfuck Interpreter", about = "A Brainfuck interpreter and recompiler")]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Opts {
-	/// Filename or brainfuck code, if terminal input is toggled.
-	file_name:                   String,
-	/// Interpret the filename as brainfuck code instead of a file path.
-	#[clap(short = 't', long = "terminal_input")]
-	terminal_input:              bool,
+	#[clap(subcommand)]
+	command:                     Option<Command>,
+	#[clap(flatten)]
+	source:                      SourceOpts,
 	/// Old interpreter: 'oi'
 	/// New interpreter: 'ni'
 	/// Recompiler: 'r'
 	#[clap(short = 'e', long = "executor", default_value = "r")]
 	executor:                    ExecutorArg,
-	/// Unsafe array: 'ua'
+	#[clap(flatten)]
+	execution:                   ExecutionOpts,
+	/// Executes the '#' instruction, printing the memory pointer and the first 10 cells to stderr.
+	#[clap(long = "debug-dump")]
+	debug_dump:                  bool,
+	/// Writes every output byte immediately, instead of buffering the output.
+	/// Useful for interactive programs.
+	#[clap(long = "unbuffered")]
+	unbuffered:                  bool,
+	/// Stops the program after this many steps.
+	/// The interpreters count instructions, the recompiler counts loop iterations.
+	#[clap(long = "max-steps")]
+	max_steps:                   Option<u64>,
+	/// Stops the program after this many seconds.
+	#[clap(long = "timeout", parse(try_from_str = parse_timeout))]
+	timeout:                     Option<std::time::Duration>,
+	/// Disables optimization passes
+	#[clap(long = "disable_optimization")]
+	disable_optimization_passes: bool,
+	/// Prints information about recompiled operands, and memory after execution
+	#[clap(short = 'v', long = "verbose")]
+	verbose:                     bool,
+	/// Writes an annotated disassembly of the recompiled machine code to this file, before running the program.
+	/// Only the recompiler creates machine code.
+	#[clap(long = "dump-asm")]
+	dump_asm:                    Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+	/// Compiles the brainfuck program to a standalone x86-64 Linux executable.
+	Compile(CompileOpts),
+	/// Translates the brainfuck program to the source code of another language.
+	Transpile(TranspileOpts),
+	/// Runs the brainfuck program on the old interpreter, stepping through it with commands from the terminal.
+	Debug(DebugOpts),
+	/// Runs the brainfuck program on the new interpreter, and reports the loops that took the most steps to stderr.
+	Profile(ProfileOpts),
+}
+
+/// Where the brainfuck program is read from, shared by every command.
+#[derive(Args, Debug)]
+struct SourceOpts {
+	/// Filename or brainfuck code, if terminal input is toggled.
+	// An Option, as running the program without a subcommand needs no file name when a subcommand is given.
+	#[clap(required = true)]
+	file_name:      Option<String>,
+	/// Interpret the filename as brainfuck code instead of a file path.
+	#[clap(short = 't', long = "terminal_input")]
+	terminal_input: bool,
+}
+impl SourceOpts {
+	fn read_code(&self) -> String {
+		let file_name = self.file_name.as_deref().unwrap_or_default();
+		match self.terminal_input {
+			false => bf_run_core::read_bf_file_to_string(file_name).unwrap(),
+			true => file_name.to_string(),
+		}
+	}
+}
+
+/// Settings of the memory, the input and the cells, shared by the commands that execute the program.
+#[derive(Args, Debug)]
+struct ExecutionOpts {
+	/// Unsafe array: 'ua' (default)
 	/// Single array: 'sa'
-	/// Dual array: 'da'
-	#[clap(short = 'm', long = "memory_type", default_value = "ua")]
-	memory_type:                 MemoryType,
+	/// Dual array: 'da' (default of the debug command)
+	/// Guarded array: 'ga'
+	/// Bounded array: 'ba'
+	#[clap(short = 'm', long = "memory_type")]
+	memory_type: Option<MemoryType>,
 	/// Sets a custom length to the internal memory of the brainfuck program.
-	/// Probably only matters with "Unsafe array" memory setting.
+	/// Probably only matters with "Unsafe array", "Guarded array" and "Bounded array" memory settings.
+	/// The "Guarded array" memory rounds the length up to a whole number of memory pages.
+	#[clap(long = "memory_size")]
+	memory_size: Option<usize>,
+	/// What moving the memory pointer before the first or after the last cell of the "Bounded array" memory does.
+	/// The memory pointer starts at the first cell.
+	/// Stop with an error: 'error'
+	/// Wrap around to the other end of the memory: 'wrap'
+	/// Stay at the end of the memory: 'clamp'
+	/// Optimizations are disabled with the "Bounded array" memory, as every move of the pointer is checked.
+	#[clap(long = "tape-edge", default_value = "error", parse(try_from_str = parse_tape_edge_policy))]
+	tape_edge:   bf_run_core::bf_memory::TapeEdgePolicy,
+	/// Number of bits in each cell of the memory: '8', '16' or '32'
+	#[clap(long = "cell-size", default_value = "8")]
+	cell_size:   CellSize,
+	/// What reading past the end of the input does to the current cell.
+	/// Leave cell unchanged: 'unchanged'
+	/// Set cell to 0: '0'
+	/// Set cell to the maximum value of a cell (-1): '255'
+	/// Stop with an error: 'error'
+	#[clap(long = "eof", default_value = "unchanged", parse(try_from_str = parse_eof_policy))]
+	eof_policy:  bf_run_core::executors::EofPolicy,
+	/// What incrementing a cell past its maximum value, or decrementing it below zero does.
+	/// Wrap around: 'wrap'
+	/// Stay at the maximum value or zero: 'saturate'
+	/// Stop with an error, reporting the position in the source: 'trap'
+	#[clap(long = "overflow", default_value = "wrap", parse(try_from_str = parse_overflow_policy))]
+	overflow:    bf_run_core::executors::OverflowPolicy,
+}
+
+/// Settings of the programs created by the compile and transpile commands.
+#[derive(Args, Debug)]
+struct CompilationOpts {
+	/// Sets the length of the memory of the created program.
+	/// The memory is not bounds checked, like the "Unsafe array" memory setting.
 	#[clap(long = "memory_size")]
 	memory_size:                 Option<usize>,
+	/// What reading past the end of the input does to the current cell.
+	/// Leave cell unchanged: 'unchanged'
+	/// Set cell to 0: '0'
+	/// Set cell to the maximum value of a cell (-1): '255'
+	/// Stop with an error: 'error'
+	#[clap(long = "eof", default_value = "unchanged", parse(try_from_str = parse_eof_policy))]
+	eof_policy:                  bf_run_core::executors::EofPolicy,
 	/// Disables optimization passes
 	#[clap(long = "disable_optimization")]
 	disable_optimization_passes: bool,
-	/// Prints information about recompiled operands, and memory after execution
-	#[clap(short = 'v', long = "verbose")]
-	verbose:                     bool,
 }
+impl CompilationOpts {
+	fn compiler_options(&self) -> bf_run_core::compilers::CompilerOptions {
+		bf_run_core::compilers::CompilerOptions {
+			enable_optimizations: !self.disable_optimization_passes,
+			memory_size:          self.memory_size,
+			eof_policy:           self.eof_policy,
+		}
+	}
+}
+
+#[derive(Args, Debug)]
+struct CompileOpts {
+	#[clap(flatten)]
+	source:      SourceOpts,
+	/// Path of the executable to create.
+	#[clap(short = 'o', long = "output")]
+	output:      String,
+	#[clap(flatten)]
+	compilation: CompilationOpts,
+}
+
+#[derive(Args, Debug)]
+struct TranspileOpts {
+	#[clap(flatten)]
+	source:      SourceOpts,
+	/// Language of the created source code.
+	/// C: 'c'
+	/// Rust program: 'rust'
+	/// Rust function "run", with the input and output as arguments: 'rust-fn'
+	/// Binary WebAssembly module, importing "env.read_byte" and "env.write_byte": 'wasm'
+	/// LLVM IR: 'llvm'
+	#[clap(long = "target")]
+	target:      TranspileTarget,
+	/// Path of the source file to create, the source code is written to stdout without it.
+	#[clap(short = 'o', long = "output")]
+	output:      Option<String>,
+	/// Number of bits in each cell of the memory: '8', '16' or '32'
+	#[clap(long = "cell-size", default_value = "8")]
+	cell_size:   CellSize,
+	#[clap(flatten)]
+	compilation: CompilationOpts,
+}
+
+#[derive(Args, Debug)]
+struct DebugOpts {
+	#[clap(flatten)]
+	source:    SourceOpts,
+	/// File that the program reads its input from.
+	/// Without it, the program reads from the terminal, like the debugger commands.
+	#[clap(short = 'i', long = "input")]
+	input:     Option<String>,
+	#[clap(flatten)]
+	execution: ExecutionOpts,
+}
+
+#[derive(Args, Debug)]
+struct ProfileOpts {
+	#[clap(flatten)]
+	source:                      SourceOpts,
+	#[clap(flatten)]
+	execution:                   ExecutionOpts,
+	/// Stops the program after this many steps, the profile of the steps taken is still reported.
+	#[clap(long = "max-steps")]
+	max_steps:                   Option<u64>,
+	/// Stops the program after this many seconds, the profile of the steps taken is still reported.
+	#[clap(long = "timeout", parse(try_from_str = parse_timeout))]
+	timeout:                     Option<std::time::Duration>,
+	/// Disables optimization passes, so every loop is profiled.
+	#[clap(long = "disable_optimization")]
+	disable_optimization_passes: bool,
+	/// Number of loops listed in the report.
+	#[clap(long = "top", default_value = "10")]
+	top:                         usize,
+	/// Also prints the source code, with the number of loop iterations in the margin.
+	#[clap(long = "annotate")]
+	annotate:                    bool,
+}
+
+/// Runs the block with Memory and Cell replaced by the memory and cell types selected in an ExecutionOpts,
+/// using default_memory_type when no memory type was given.
+/// Further type replacements of static_dispatch can be given before the block.
+macro_rules! dispatch_memory {
+	($execution:expr, $default_memory_type:expr, $($replacements_and_block:tt)*) => {{
+		use bf_run_core::bf_memory::*;
+		use CellSize::*;
+		use MemoryType::*;
+		static_dispatch!(
+			(Memory, $execution.memory_type.unwrap_or($default_memory_type))[(DualArrayArg, BfMemoryMemSafe) (SingleArrayArg, BfMemoryMemSafeSingleArray) (UnsafeArrayArg, BfMemoryMemUnsafe) (GuardedArrayArg, BfMemoryMemGuarded) (BoundedArrayArg, BfMemoryBounded)]
+			(Cell, $execution.cell_size)[(Cell8Arg, u8) (Cell16Arg, u16) (Cell32Arg, u32)]
+			$($replacements_and_block)*
+		)
+	}};
+}
+
 fn main() {
 	let opts = Opts::parse();
 
-	let code = match opts.terminal_input {
-		false => bf_run_core::read_bf_file_to_string(&opts.file_name).unwrap(),
-		true => opts.file_name.clone(),
+	match &opts.command {
+		Some(Command::Compile(compile_opts)) => compile(compile_opts),
+		Some(Command::Transpile(transpile_opts)) => transpile(transpile_opts),
+		Some(Command::Debug(debug_opts)) => debug(debug_opts),
+		Some(Command::Profile(profile_opts)) => profile(profile_opts),
+		None => run(&opts),
+	}
+}
+
+fn compile(opts: &CompileOpts) {
+	let code = opts.source.read_code();
+	let compiler = bf_run_core::compilers::ElfCompiler::new(&code, opts.compilation.compiler_options()).unwrap_or_else(|err| {
+		eprintln!("{}", err.diagnostic());
+		std::process::exit(1);
+	});
+	if let Err(err) = write_executable(&opts.output, &compiler.compile()) {
+		eprintln!("Error writing '{}': {}", opts.output, err);
+		std::process::exit(1);
+	}
+}
+
+fn transpile(opts: &TranspileOpts) {
+	use bf_run_core::compilers::{CTranspiler, LlvmTranspiler, RustTranspiler, WasmCompiler};
+	let code = opts.source.read_code();
+	let options = opts.compilation.compiler_options();
+
+	use CellSize::*;
+	use TranspileTarget::*;
+	let output = static_dispatch!(
+		(Cell, opts.cell_size)[(Cell8Arg, u8) (Cell16Arg, u16) (Cell32Arg, u32)]
+		{
+			match opts.target {
+				C => CTranspiler::<Cell>::new(&code, options).map(|transpiler| transpiler.transpile().into_bytes()),
+				Rust => RustTranspiler::<Cell>::new(&code, options).map(|transpiler| transpiler.transpile().into_bytes()),
+				RustFunction => {
+					RustTranspiler::<Cell>::new(&code, options).map(|transpiler| transpiler.transpile_function().into_bytes())
+				},
+				Wasm => WasmCompiler::<Cell>::new(&code, options).map(|compiler| compiler.compile()),
+				Llvm => LlvmTranspiler::<Cell>::new(&code, options).map(|transpiler| transpiler.transpile().into_bytes()),
+			}
+		}
+	)
+	.unwrap_or_else(|err| {
+		eprintln!("{}", err.diagnostic());
+		std::process::exit(1);
+	});
+	let result = match &opts.output {
+		Some(path) => std::fs::write(path, output),
+		None => std::io::Write::write_all(&mut std::io::stdout(), &output),
+	};
+	if let Err(err) = result {
+		eprintln!("Error writing '{}': {}", opts.output.as_deref().unwrap_or("stdout"), err);
+		std::process::exit(1);
+	}
+}
+
+fn debug(opts: &DebugOpts) {
+	let code = opts.source.read_code();
+	let execution = &opts.execution;
+	let input: Box<dyn std::io::Read> = match &opts.input {
+		Some(path) => match std::fs::File::open(path) {
+			Ok(file) => Box::new(file),
+			Err(err) => {
+				eprintln!("Error opening '{}': {}", path, err);
+				std::process::exit(1);
+			},
+		},
+		None => Box::new(std::io::stdin()),
+	};
+	let bf_io = bf_run_core::executors::BfIo::new(input, std::io::stdout())
+		.with_eof_policy(execution.eof_policy)
+		.with_unbuffered_output();
+	let options = bf_run_core::executors::ExecutorOptions { overflow: execution.overflow, ..Default::default() };
+
+	use bf_run_core::executors::*;
+	dispatch_memory!(execution, DualArrayArg, {
+		let bf_memory = Memory::<Cell>::new(execution.memory_size).with_edge_policy(execution.tape_edge);
+		let interpreter = BfInterpreter::new(code, bf_memory, bf_io, options).unwrap_or_else(|err| {
+			eprintln!("{}", err.diagnostic());
+			std::process::exit(1);
+		});
+		debugger::Debugger::new(interpreter).run();
+	});
+}
+
+fn profile(opts: &ProfileOpts) {
+	let code = opts.source.read_code();
+	let execution = &opts.execution;
+	let bf_io = bf_run_core::executors::BfIo::stdio().with_eof_policy(execution.eof_policy);
+	let options = bf_run_core::executors::ExecutorOptions {
+		enable_optimizations: !opts.disable_optimization_passes,
+		fuel: opts.max_steps,
+		timeout: opts.timeout,
+		overflow: execution.overflow,
+		..Default::default()
 	};
 
+	use bf_run_core::executors::*;
+	dispatch_memory!(execution, UnsafeArrayArg, {
+		let bf_memory = Memory::<Cell>::new(execution.memory_size).with_edge_policy(execution.tape_edge);
+		warn_if_not_optimized(&bf_memory, options.enable_optimizations);
+		let interpreter = BfOptInterpreter::new(code.clone(), bf_memory, bf_io, options).unwrap_or_else(|err| {
+			eprintln!("{}", err.diagnostic());
+			std::process::exit(1);
+		});
+		let (result, loops) = interpreter.start_profiled();
+		if let RunStatus::Error(err) = result.status {
+			eprintln!("\nError: {}", err);
+		}
+		profiler::print_report(&code, &loops, result.steps.unwrap_or_default(), opts.top);
+		if opts.annotate {
+			profiler::print_annotated_source(&code, &loops);
+		}
+	});
+}
+
+/// Tells that the optimization passes are skipped, when they are enabled but the memory does not allow them.
+fn warn_if_not_optimized<M: bf_run_core::bf_memory::BfMemory>(bf_memory: &M, enable_optimizations: bool) {
+	if enable_optimizations && !bf_memory.allows_optimizations() {
+		eprintln!("The \"Bounded array\" memory checks every move of the memory pointer, running without optimization passes.");
+	}
+}
+
+fn write_disassembly(path: &str, disassembly: Option<String>) {
+	match disassembly {
+		Some(disassembly) => {
+			if let Err(err) = std::fs::write(path, disassembly) {
+				eprintln!("Error writing '{}': {}", path, err);
+				std::process::exit(1);
+			}
+		},
+		None => eprintln!("Only the recompiler creates machine code, \"--dump-asm\" is ignored."),
+	}
+}
+
+fn write_executable(path: &str, contents: &[u8]) -> std::io::Result<()> {
+	std::fs::write(path, contents)?;
+	#[cfg(unix)]
 	{
-		use bf_run_core::{bf_memory::*, executors::*};
+		use std::os::unix::fs::PermissionsExt;
+		std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+	}
+	Ok(())
+}
+
+fn run(opts: &Opts) {
+	let code = opts.source.read_code();
+	let execution = &opts.execution;
+
+	{
+		use bf_run_core::executors::*;
 		use ExecutorArg::*;
-		use MemoryType::*;
-		static_dispatch!(
-			(Memory, opts.memory_type)[(DualArrayArg, BfMemoryMemSafe) (SingleArrayArg, BfMemoryMemSafeSingleArray) (UnsafeArrayArg, BfMemoryMemUnsafe)]
-			(Executor, opts.executor)[(NewInterpreterArg, BfOptInterpreter) (OldInterpreterArg, BfInterpreter) (RecompilerArg, BfRecompiler)]
+		let executor = match opts.executor {
+			RecompilerArg if !RECOMPILER_SUPPORTED => {
+				eprintln!("The recompiler is not supported on this processor architecture, using the new interpreter instead.");
+				NewInterpreterArg
+			},
+			executor => executor,
+		};
+		dispatch_memory!(
+			execution,
+			UnsafeArrayArg,
+			(Executor, executor)[(NewInterpreterArg, BfOptInterpreter) (OldInterpreterArg, BfInterpreter) (RecompilerArg, BfRecompiler)]
 			{
-				let executor = Executor::new(code, Memory::new(opts.memory_size), !opts.disable_optimization_passes, opts.verbose);
-				executor.start();
+				let bf_memory = Memory::<Cell>::new(execution.memory_size).with_edge_policy(execution.tape_edge);
+				let mut bf_io = BfIo::stdio().with_eof_policy(execution.eof_policy);
+				if opts.unbuffered {
+					bf_io = bf_io.with_unbuffered_output();
+				}
+				let options = ExecutorOptions {
+					enable_optimizations: !opts.disable_optimization_passes,
+					verbose: opts.verbose,
+					fuel: opts.max_steps,
+					timeout: opts.timeout,
+					overflow: execution.overflow,
+					debug_dump: opts.debug_dump,
+				};
+				if !matches!(executor, OldInterpreterArg) {
+					warn_if_not_optimized(&bf_memory, options.enable_optimizations);
+				}
+				let executor = Executor::new(code, bf_memory, bf_io, options)
+					.unwrap_or_else(|err| {
+						eprintln!("{}", err.diagnostic());
+						std::process::exit(1);
+					});
+				if let Some(path) = &opts.dump_asm {
+					write_disassembly(path, executor.disassembly());
+				}
+				if let RunStatus::Error(err) = executor.start().status {
+					eprintln!("\nError: {}", err);
+					std::process::exit(1);
+				}
 			}
 		);
 	}
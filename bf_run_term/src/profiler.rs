@@ -0,0 +1,75 @@
+/*
+	This file is part of bf_run.
+
+	bf_run is free software: you can redistribute it and/or modify
+	it under the terms of the GNU General Public License as published by
+	the Free Software Foundation, either version 3 of the License, or
+	(at your option) any later version.
+
+	bf_run is distributed in the hope that it will be useful,
+	but WITHOUT ANY WARRANTY; without even the implied warranty of
+	MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+	GNU General Public License for more details.
+
+	You should have received a copy of the GNU General Public License
+	along with bf_run.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use bf_run_core::executors::{operations::SourcePosition, LoopProfile};
+
+/// Number of characters of the source of a loop, shown in the report.
+const LOOP_SOURCE_LENGTH: usize = 40;
+
+/// Prints the loops with the most steps to stderr, with their position and source.
+pub(crate) fn print_report(code: &str, loops: &[LoopProfile], total_steps: u64, top: usize) {
+	let mut hottest = loops.iter().collect::<Vec<_>>();
+	hottest.sort_by_key(|loop_profile| std::cmp::Reverse(loop_profile.steps));
+	eprintln!("\nSteps taken: {}, loops that were not replaced by the optimizer: {}", total_steps, loops.len());
+	if loops.is_empty() {
+		return;
+	}
+	eprintln!("{:>14} {:>7} {:>12} {:>10}  {:<22} Loop", "Steps", "Share", "Iterations", "Entries", "Position");
+	for loop_profile in hottest.into_iter().take(top) {
+		let share = loop_profile.steps as f64 * 100.0 / total_steps.max(1) as f64;
+		let position = SourcePosition::from_byte_offset(code, loop_profile.span.start);
+		let marker = if loop_profile.only_modifies { "  *" } else { "" };
+		eprintln!(
+			"{:>14} {:>6.2}% {:>12} {:>10}  {:<22} {}{}",
+			loop_profile.steps,
+			share,
+			loop_profile.iterations,
+			loop_profile.entries,
+			format!("{}:{}", position.line, position.column),
+			loop_source(code, loop_profile),
+			marker
+		);
+	}
+	if loops.iter().any(|loop_profile| loop_profile.only_modifies) {
+		eprintln!("* Only modifies cells and moves the pointer, but could not be replaced by a clear or multiplication.");
+	}
+}
+
+/// Prints the source code to stderr, with the number of iterations of the loops starting on each line in the margin.
+pub(crate) fn print_annotated_source(code: &str, loops: &[LoopProfile]) {
+	let mut line_iterations = vec![None; code.lines().count()];
+	for loop_profile in loops {
+		let line = SourcePosition::from_byte_offset(code, loop_profile.span.start).line;
+		*line_iterations[line - 1].get_or_insert(0) += loop_profile.iterations;
+	}
+	eprintln!("\nIterations of the loops starting on each line:");
+	for (line, iterations) in code.lines().zip(line_iterations) {
+		match iterations {
+			Some(iterations) => eprintln!("{:>14} | {}", iterations, line),
+			None => eprintln!("{:>14} | {}", "", line),
+		}
+	}
+}
+
+/// The start of the source code of the loop, on a single line.
+fn loop_source(code: &str, loop_profile: &LoopProfile) -> String {
+	let source = code[loop_profile.span.start..loop_profile.span.end].split_whitespace().collect::<String>();
+	match source.char_indices().nth(LOOP_SOURCE_LENGTH) {
+		Some((index, _)) => format!("{}...", &source[..index]),
+		None => source,
+	}
+}
@@ -168,11 +168,11 @@ fn build_structure_block(type_replacement: &TypeReplacement, block: quote::__pri
 		let appendee = replace_type_param(appendee, name, type_variant);
 		structure.append_all(appendee);
 	}
-	return quote! {
+	quote! {
 		match #actual_enum {
 			#structure
 		}
-	};
+	}
 }
 fn replace_type_param(block: quote::__private::TokenStream, to_be_replaced: &Ident, replace_with: &Type) -> quote::__private::TokenStream {
 	let to_be_replaced_str = to_be_replaced.to_string();